@@ -0,0 +1,103 @@
+//! Save the debugger scripts to the non-volatile memory of the instrument so that they
+//! only need to be uploaded when they change.
+//!
+//! Each cached script is tagged with a content hash in a trailing comment so that the
+//! hash can be read back from the script source on later sessions.
+use sha2::{Digest, Sha256};
+
+/// The name the cached copy of `tspdbg` is saved under on the instrument.
+pub const CACHED_TSPDBG: &str = "kic_tspdbg";
+/// The name the cached copy of `kiDebugger` is saved under on the instrument.
+pub const CACHED_KIDEBUGGER: &str = "kic_kiDebugger";
+
+/// Marks the start of the cache query response printed by the instrument.
+pub const CACHE_BEGIN: &str = "<kic-debugger-cache>";
+/// Marks the end of the cache query response printed by the instrument.
+pub const CACHE_END: &str = "</kic-debugger-cache>";
+
+const TAG_PREFIX: &str = "-- kic-cache ";
+
+/// The hex-encoded SHA-256 hash of the given script source.
+#[must_use]
+pub fn content_hash(source: &str) -> String {
+    Sha256::digest(source.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Append the hash tag to a script source. The tag is appended rather than prepended
+/// so that line numbers in the script are unchanged.
+#[must_use]
+pub fn tag(source: &str, hash: &str) -> String {
+    format!("{source}\n{TAG_PREFIX}{hash}\n")
+}
+
+/// The hashes of the debugger scripts cached on the instrument.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CachedHashes {
+    /// The hash of the cached `tspdbg`, if there is one
+    pub tspdbg: Option<String>,
+    /// The hash of the cached `kiDebugger`, if there is one
+    pub ki_debugger: Option<String>,
+}
+
+impl CachedHashes {
+    /// The TSP command that makes the instrument print the hashes of the cached scripts.
+    #[must_use]
+    pub fn query() -> String {
+        format!(
+            "do local function h(s) if s and s.source then local _, _, v = string.find(s.source, \"{}(%x+)%s*$\") return v end end print(\"{CACHE_BEGIN}\" .. tostring(h({CACHED_TSPDBG})) .. \"|\" .. tostring(h({CACHED_KIDEBUGGER})) .. \"{CACHE_END}\") end\n",
+            TAG_PREFIX.replace('-', "%-")
+        )
+    }
+
+    /// Find and parse the cache query response in the given instrument output.
+    ///
+    /// Returns `None` if the output does not contain a complete response.
+    #[must_use]
+    pub fn parse(output: &str) -> Option<Self> {
+        let end = output.rfind(CACHE_END)?;
+        let begin = output[..end].rfind(CACHE_BEGIN)?;
+        let response = &output[begin.checked_add(CACHE_BEGIN.len())?..end];
+
+        let (tspdbg, ki_debugger) = response.split_once('|')?;
+        let hash = |h: &str| {
+            let h = h.trim();
+            (h != "nil" && !h.is_empty()).then(|| h.to_string())
+        };
+
+        Some(Self {
+            tspdbg: hash(tspdbg),
+            ki_debugger: hash(ki_debugger),
+        })
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::{content_hash, tag, CachedHashes};
+
+    #[test]
+    fn parse_cached_hashes() {
+        let hash = content_hash("print('hi')");
+        let output = format!("<kic-debugger-cache>{hash}|nil</kic-debugger-cache>\n");
+
+        assert_eq!(
+            CachedHashes::parse(&output),
+            Some(CachedHashes {
+                tspdbg: Some(hash),
+                ki_debugger: None,
+            })
+        );
+    }
+
+    #[test]
+    fn tag_keeps_line_numbers() {
+        let source = "line1\nline2\n";
+        let tagged = tag(source, &content_hash(source));
+
+        assert!(tagged.starts_with(source));
+        assert!(tagged.trim_end().ends_with(&content_hash(source)));
+    }
+}
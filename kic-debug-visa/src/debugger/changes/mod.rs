@@ -0,0 +1,245 @@
+//! Find the variables and watch values that changed between two stops of the debuggee.
+//!
+//! Each stop is reported by the on-instrument debugger as a frame with a `<stacks>`
+//! element, holding the locals, upvalues, globals and watchpoints of every stack level.
+//! Tables are expanded from their `tableData` so changes to nested fields are found too.
+use std::collections::BTreeMap;
+
+use super::output::{parse_attributes, FRAME_END, FRAME_START};
+
+/// Identifies a variable across stops.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct VariableKey {
+    /// The function the variable belongs to, empty for globals
+    pub func: String,
+    /// `locals`, `upvalues`, `globals` or `watchpoints`
+    pub scope: String,
+    /// The name of the variable or the watch expression, with table fields appended,
+    /// escaped as in the debugger frames
+    pub name: String,
+}
+
+/// A variable as seen at a stop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VariableState {
+    /// The stack level the variable was found at
+    pub level: u32,
+    /// The value of the variable, escaped as in the debugger frames
+    pub value: String,
+}
+
+/// A variable whose value differs from the previous stop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub key: VariableKey,
+    pub level: u32,
+    pub value: String,
+    /// The value at the previous stop, `None` for a new table field
+    pub previous: Option<String>,
+}
+
+/// The variables at one stop of the debuggee.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    variables: BTreeMap<VariableKey, VariableState>,
+}
+
+impl Snapshot {
+    /// Parse the variables from a debugger frame.
+    ///
+    /// Returns `None` if the frame doesn't describe a stop.
+    #[must_use]
+    pub fn parse(frame: &str) -> Option<Self> {
+        if !frame.contains(FRAME_START) || !frame.contains("<stacks>") {
+            return None;
+        }
+        let mut snapshot = Self::default();
+        let mut func = String::new();
+        let mut level = 0;
+        for line in frame.lines().map(str::trim) {
+            let Some((element, attributes)) =
+                line.strip_prefix('<').and_then(|l| l.split_once(' '))
+            else {
+                continue;
+            };
+            let attributes = parse_attributes(attributes);
+            let attribute = |name: &str| {
+                attributes
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .map(|(_, v)| v.clone())
+            };
+            let (scope, name_attribute) = match element {
+                "stack" => {
+                    func = attribute("func").unwrap_or_default();
+                    level = attribute("level").and_then(|l| l.parse().ok()).unwrap_or(0);
+                    continue;
+                }
+                "local" => ("locals", "name"),
+                "upvalue" => ("upvalues", "name"),
+                "global" => ("globals", "name"),
+                "watchpoint" => ("watchpoints", "expression"),
+                _ => continue,
+            };
+            let (Some(name), Some(value)) = (attribute(name_attribute), attribute("value")) else {
+                continue;
+            };
+            let key = VariableKey {
+                // Globals are the same in every stack level.
+                func: if scope == "globals" {
+                    String::new()
+                } else {
+                    func.clone()
+                },
+                scope: scope.to_string(),
+                name,
+            };
+            if let Some(table_data) = attribute("tableData") {
+                if let Ok(table) = serde_json::from_str(&table_data) {
+                    snapshot.insert_fields(&key, level, &table);
+                }
+            }
+            snapshot
+                .variables
+                .entry(key)
+                .or_insert(VariableState { level, value });
+        }
+        Some(snapshot)
+    }
+
+    /// Add the fields of a table from its `tableData`, which looks like
+    /// `{ "table" : [{ "name": "x", "value": 1 }, ...] }`.
+    fn insert_fields(&mut self, parent: &VariableKey, level: u32, table: &serde_json::Value) {
+        let Some(fields) = table.get("table").and_then(|t| t.as_array()) else {
+            return;
+        };
+        for field in fields {
+            let (Some(name), Some(value)) = (field.get("name"), field.get("value")) else {
+                continue;
+            };
+            let name = match name {
+                serde_json::Value::String(s) => format!("{}.{}", parent.name, escape(s)),
+                other => format!("{}[{other}]", parent.name),
+            };
+            let key = VariableKey {
+                name,
+                ..parent.clone()
+            };
+            if value.get("table").is_some() {
+                self.insert_fields(&key, level, value);
+            }
+            let value = match value {
+                serde_json::Value::String(s) => escape(s),
+                serde_json::Value::Object(_) => "table".to_string(),
+                other => escape(&other.to_string()),
+            };
+            self.variables
+                .entry(key)
+                .or_insert(VariableState { level, value });
+        }
+    }
+
+    /// The variables whose values differ from `previous`. Table fields that are new are
+    /// included, other new variables (e.g. the locals of a function that was just
+    /// entered) are not.
+    #[must_use]
+    pub fn changes_since(&self, previous: &Self) -> Vec<Change> {
+        self.variables
+            .iter()
+            .filter_map(|(key, state)| {
+                let before = previous.variables.get(key);
+                let changed = match before {
+                    Some(before) => before.value != state.value,
+                    None => key.name.rfind(['.', '[']).is_some_and(|i| {
+                        previous.variables.contains_key(&VariableKey {
+                            name: key.name[..i].to_string(),
+                            ..key.clone()
+                        })
+                    }),
+                };
+                changed.then(|| Change {
+                    key: key.clone(),
+                    level: state.level,
+                    value: state.value.clone(),
+                    previous: before.map(|b| b.value.clone()),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Render changes as a debugger frame for front ends.
+#[must_use]
+pub fn render_frame(changes: &[Change]) -> String {
+    let mut frame = format!("{FRAME_START}\n  <changes>\n");
+    for change in changes {
+        frame.push_str(&format!(
+            "    <changed scope='{}' level='{}' name='{}' value='{}'",
+            change.key.scope, change.level, change.key.name, change.value
+        ));
+        if let Some(previous) = &change.previous {
+            frame.push_str(&format!(" previous='{previous}'"));
+        }
+        frame.push_str(" />\n");
+    }
+    frame.push_str(&format!("  </changes>\n{FRAME_END}\n"));
+    frame
+}
+
+/// Render changes as a single line for the command line.
+#[must_use]
+pub fn render_summary(changes: &[Change]) -> String {
+    let changes: Vec<String> = changes
+        .iter()
+        .map(|c| match &c.previous {
+            Some(previous) => format!("{} = {} (was {previous})", c.key.name, c.value),
+            None => format!("{} = {} (new)", c.key.name, c.value),
+        })
+        .collect();
+    format!("Changed: {}", changes.join(", "))
+}
+
+/// Escape a value for an attribute like the on-instrument debugger does.
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\'', "&apos;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod unit {
+    use super::{Snapshot, FRAME_END, FRAME_START};
+
+    fn stop(x: &str, y: u32, extra: &str) -> String {
+        format!(
+            "{FRAME_START}\n  <stacks>\n    <stack level='0' currentline='3' func='function: 0x1'>\n  <locals>\n    <local name='x' value='{x}' type='number' />\n    <local name='t' value='table: 0x2' type='table' tableData='{{ \"table\" : [{{ \"name\":\"y\", \"value\" :{y}}}{extra}]}}' />\n  </locals>\n    </stack>\n  </stacks>\n{FRAME_END}"
+        )
+    }
+
+    #[test]
+    fn nested_fields_are_compared() {
+        let before = Snapshot::parse(&stop("1", 2, "")).unwrap();
+        let after =
+            Snapshot::parse(&stop("1", 3, ",{ \"name\":\"it's\", \"value\" :\"new\"}")).unwrap();
+
+        let changes = after.changes_since(&before);
+        let names: Vec<&str> = changes.iter().map(|c| c.key.name.as_str()).collect();
+        assert_eq!(names, ["t.it&apos;s", "t.y"]);
+        assert_eq!(changes[1].previous.as_deref(), Some("2"));
+        assert_eq!(changes[0].previous, None);
+    }
+
+    #[test]
+    fn other_frames_are_not_stops() {
+        assert!(
+            Snapshot::parse(&format!("{FRAME_START}<setLocalVariable />{FRAME_END}")).is_none()
+        );
+        assert!(Snapshot::parse(&stop("1", 2, ""))
+            .unwrap()
+            .changes_since(&Snapshot::parse(&stop("1", 2, "")).unwrap())
+            .is_empty());
+    }
+}
@@ -0,0 +1,182 @@
+//! Crash reports that are written when the debuggee fails or the session ends with an
+//! IO error, so that a bug against a script or the instrument firmware can be filed
+//! with everything needed to look into it.
+//!
+//! A report is a single JSON file holding the instrument information, the debugged
+//! source and its hash, the breakpoints and watchpoints, the last stop, the drained
+//! instrument error queue and the tail of the instrument output.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use kic_lib::instrument::info::InstrumentInfo;
+use serde::Serialize;
+
+use super::{breakpoint::Breakpoint, cache::content_hash, watchpoint::WatchpointInfo};
+use crate::error::Result;
+
+/// Marks the start of the error queue printed by the instrument.
+pub const ERRORS_BEGIN: &str = "<kic-debugger-errors>";
+/// Marks the end of the error queue printed by the instrument.
+pub const ERRORS_END: &str = "</kic-debugger-errors>";
+
+/// The directory crash reports are written to unless another one is given.
+#[must_use]
+pub fn default_dir() -> PathBuf {
+    std::env::temp_dir().join("kic-debug").join("crash-reports")
+}
+
+/// An entry of the instrument error queue.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct QueuedError {
+    pub code: String,
+    pub message: String,
+    pub severity: String,
+    pub node: String,
+}
+
+/// The TSP command that prints and removes the entries of the error queue.
+#[must_use]
+pub fn error_queue_query() -> String {
+    format!(
+        "do print(\"{ERRORS_BEGIN}\") while errorqueue.count > 0 do local c, m, s, n = errorqueue.next() print(tostring(c) .. \"\\t\" .. tostring(s) .. \"\\t\" .. tostring(n) .. \"\\t\" .. tostring(m)) end print(\"{ERRORS_END}\") end\n"
+    )
+}
+
+/// Parse the error queue printed by [`error_queue_query`].
+#[must_use]
+pub fn parse_error_queue(output: &str) -> Vec<QueuedError> {
+    let Some((_, rest)) = output.rsplit_once(ERRORS_BEGIN) else {
+        return Vec::new();
+    };
+    let entries = rest.split_once(ERRORS_END).map_or(rest, |(e, _)| e);
+    entries
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(4, '\t');
+            Some(QueuedError {
+                code: fields.next()?.trim().to_string(),
+                severity: fields.next()?.to_string(),
+                node: fields.next()?.to_string(),
+                message: fields.next()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// The debugged script.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ScriptReport {
+    /// The name of the script on the instrument
+    pub name: Option<String>,
+    /// The local file of the script
+    pub path: Option<PathBuf>,
+    /// The SHA-256 hash of the source
+    pub hash: String,
+    pub source: String,
+}
+
+impl ScriptReport {
+    #[must_use]
+    pub fn new(name: Option<String>, path: Option<PathBuf>, source: String) -> Self {
+        Self {
+            name,
+            path,
+            hash: content_hash(&source),
+            source,
+        }
+    }
+}
+
+/// Everything known about a failed session.
+#[derive(Serialize, Debug, Clone)]
+pub struct CrashReport {
+    /// When the report was created, in UTC
+    pub created: String,
+    /// The version of this application
+    pub kic_debug_version: String,
+    /// Why the session failed
+    pub reason: String,
+    /// `None` if the instrument could not be identified
+    pub instrument: Option<InstrumentInfo>,
+    pub script: Option<ScriptReport>,
+    pub breakpoints: Vec<Breakpoint>,
+    pub watchpoints: Vec<WatchpointInfo>,
+    /// The frame with the stack and variables of the last stop
+    pub last_stop: Option<String>,
+    /// The error queue, empty if it could not be read
+    pub error_queue: Vec<QueuedError>,
+    /// The latest instrument output
+    pub output_tail: String,
+}
+
+impl CrashReport {
+    /// Start a report for a session that failed for `reason`.
+    #[must_use]
+    pub fn new(reason: &str) -> Self {
+        Self {
+            created: Utc::now().to_rfc3339(),
+            kic_debug_version: crate::VERSION.to_string(),
+            reason: reason.to_string(),
+            instrument: None,
+            script: None,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            last_stop: None,
+            error_queue: Vec::new(),
+            output_tail: String::new(),
+        }
+    }
+
+    /// Write the report to a new file in `dir`.
+    ///
+    /// # Returns
+    /// The path of the report.
+    ///
+    /// # Errors
+    /// Returns an IO error if the report can't be written.
+    pub fn write(&self, dir: &Path) -> Result<PathBuf> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!(
+            "kic-debug-crash-{}.json",
+            Utc::now().format("%Y%m%d-%H%M%S%.3f")
+        ));
+        fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::{parse_error_queue, CrashReport, ScriptReport, ERRORS_BEGIN, ERRORS_END};
+
+    #[test]
+    fn error_queue_is_parsed() {
+        let output = format!(
+            "{ERRORS_BEGIN}\n-285\t2\t1\tTSP Syntax error at line 3: unexpected symbol\n1100\t1\t1\tCommand is\tdisabled\n{ERRORS_END}"
+        );
+        let errors = parse_error_queue(&output);
+
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].code, "-285");
+        assert_eq!(errors[1].message, "Command is\tdisabled");
+        assert!(parse_error_queue("nothing").is_empty());
+    }
+
+    #[test]
+    fn report_is_written() {
+        let dir = std::env::temp_dir().join(format!("kic-debug-crash-test-{}", std::process::id()));
+        let mut report = CrashReport::new("execution failed");
+        report.script = Some(ScriptReport::new(None, None, "x = 1".to_string()));
+
+        let path = report.write(&dir).unwrap();
+        let written: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(written["reason"], "execution failed");
+        assert_eq!(written["script"]["hash"].as_str().unwrap().len(), 64);
+    }
+}
@@ -0,0 +1,170 @@
+//! The front end that the debugger reads user commands from and writes its output to.
+//!
+//! By default this is stdin/stdout. In listen mode a front end connects over a local TCP
+//! socket instead, and can detach and re-attach without ending the debug session.
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+/// A source of user command lines.
+pub trait CommandSource: Send {
+    /// Block until the next line of user input has been appended to `buf`.
+    ///
+    /// # Returns
+    /// The number of bytes read, 0 at the end of the input.
+    ///
+    /// # Errors
+    /// Returns an IO error if the input could not be read.
+    fn read_line(&mut self, buf: &mut String) -> io::Result<usize>;
+}
+
+/// Read user commands from stdin.
+#[derive(Debug, Default)]
+pub struct StdinSource;
+
+impl CommandSource for StdinSource {
+    fn read_line(&mut self, buf: &mut String) -> io::Result<usize> {
+        io::stdin().read_line(buf)
+    }
+}
+
+/// The currently attached TCP front end, shared between its reader and writer.
+type Attached = Arc<Mutex<Option<TcpStream>>>;
+
+/// Accept front ends on a local TCP socket. When a front end disconnects, the next one
+/// is accepted.
+#[derive(Debug)]
+pub struct TcpFrontEnd {
+    listener: TcpListener,
+    attached: Attached,
+    reader: Option<BufReader<TcpStream>>,
+}
+
+impl TcpFrontEnd {
+    /// Listen for front ends on `addr`.
+    ///
+    /// # Errors
+    /// Returns an IO error if the address can't be bound.
+    pub fn bind(addr: SocketAddr) -> io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr)?,
+            attached: Arc::default(),
+            reader: None,
+        })
+    }
+
+    /// The address the front ends should connect to.
+    ///
+    /// # Errors
+    /// Returns an IO error if the address can't be determined.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// A writer for the output to the attached front end.
+    #[must_use]
+    pub fn output(&self) -> TcpFrontEndOutput {
+        TcpFrontEndOutput {
+            attached: Arc::clone(&self.attached),
+        }
+    }
+
+    fn attach(&mut self) -> io::Result<()> {
+        let (stream, _) = self.listener.accept()?;
+        *self.attached.lock().unwrap_or_else(PoisonError::into_inner) = Some(stream.try_clone()?);
+        self.reader = Some(BufReader::new(stream));
+        Ok(())
+    }
+
+    fn detach(&mut self) {
+        self.reader = None;
+        *self.attached.lock().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+}
+
+impl CommandSource for TcpFrontEnd {
+    fn read_line(&mut self, buf: &mut String) -> io::Result<usize> {
+        loop {
+            let Some(reader) = &mut self.reader else {
+                self.attach()?;
+                continue;
+            };
+            match reader.read_line(buf) {
+                Ok(0) | Err(_) => self.detach(),
+                Ok(read) => return Ok(read),
+            }
+        }
+    }
+}
+
+/// Writes to the attached TCP front end. Output is dropped while no front end is
+/// attached.
+#[derive(Debug, Clone)]
+pub struct TcpFrontEndOutput {
+    attached: Attached,
+}
+
+impl Write for TcpFrontEndOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut attached = self.attached.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(stream) = attached.as_mut() {
+            if stream.write_all(buf).is_err() {
+                *attached = None;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut attached = self.attached.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(stream) = attached.as_mut() {
+            if stream.flush().is_err() {
+                *attached = None;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpStream,
+        thread,
+    };
+
+    use super::{CommandSource, TcpFrontEnd};
+
+    #[test]
+    fn front_ends_can_reattach() {
+        let mut front_end = TcpFrontEnd::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = front_end.local_addr().unwrap();
+        let mut output = front_end.output();
+
+        let client = thread::spawn(move || {
+            TcpStream::connect(addr)
+                .unwrap()
+                .write_all(b".debug run\n")
+                .unwrap();
+            let mut second = TcpStream::connect(addr).unwrap();
+            second.write_all(b".debug exit\n").unwrap();
+            let mut reply = String::new();
+            BufReader::new(second).read_line(&mut reply).unwrap();
+            reply
+        });
+
+        let mut line = String::new();
+        front_end.read_line(&mut line).unwrap();
+        assert_eq!(line, ".debug run\n");
+
+        line.clear();
+        front_end.read_line(&mut line).unwrap();
+        assert_eq!(line, ".debug exit\n");
+
+        output.write_all(b"bye\n").unwrap();
+        assert_eq!(client.join().unwrap(), "bye\n");
+    }
+}
@@ -0,0 +1,171 @@
+//! Verify that the debugger scripts running on the instrument are the ones that were
+//! uploaded by this application and not a stale copy saved on the instrument.
+use crate::{
+    error::{DebugError, Result},
+    VERSION,
+};
+
+/// Marks the start of the handshake response printed by the instrument.
+pub const HANDSHAKE_BEGIN: &str = "<kic-debugger-handshake>";
+/// Marks the end of the handshake response printed by the instrument.
+pub const HANDSHAKE_END: &str = "</kic-debugger-handshake>";
+
+/// The global functions that `kiDebugger` defines and that the debugger relies on.
+pub const KI_DEBUGGER_GLOBAL_FUNCTIONS: &[&str] = &[
+    "kiClearBreakpoints",
+    "kiSetBreakpoint",
+    "kiSetWatchpoint",
+    "kiClearWatchpoints",
+    "kiClearWatchpoint",
+    "kiExecuteWithDebugger",
+    "kiSetUpVariable",
+    "kiSetLocalVariable",
+    "kiSetGlobalVariable",
+    "kiSetStopOnExit",
+    "kiMock",
+    "kiRestoreMocks",
+    "kiSetBreakOnError",
+    "kiSetPausable",
+    "kiPause",
+    "kiStatus",
+];
+
+/// The version and capabilities reported by the debugger loaded on the instrument.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebuggerVersion {
+    /// The version stamped into `kiDebugger`, `"nil"` if the script has no stamp.
+    pub ki_debugger: String,
+    /// The version stamped into `tspdbg`, `"nil"` if the script has no stamp.
+    pub tspdbg: String,
+    /// The `kiDebugger` global functions that are defined on the instrument.
+    pub capabilities: Vec<String>,
+}
+
+impl DebuggerVersion {
+    /// The TSP command that makes the instrument print the handshake response.
+    #[must_use]
+    pub fn query() -> String {
+        let functions = KI_DEBUGGER_GLOBAL_FUNCTIONS
+            .iter()
+            .map(|f| format!("\"{f}\""))
+            .collect::<Vec<_>>()
+            .join(",");
+        format!(
+            "do local c = {{}} for _, f in ipairs({{{functions}}}) do if type(_G[f]) == \"function\" then table.insert(c, f) end end local v = kiDebuggerVersion or {{}} print(\"{HANDSHAKE_BEGIN}\" .. tostring(v.kic) .. \"|\" .. tostring(v.tspdbg) .. \"|\" .. table.concat(c, \",\") .. \"{HANDSHAKE_END}\") end\n"
+        )
+    }
+
+    /// Find and parse the handshake response in the given instrument output.
+    ///
+    /// Returns `None` if the output does not contain a complete handshake response.
+    #[must_use]
+    pub fn parse(output: &str) -> Option<Self> {
+        let end = output.rfind(HANDSHAKE_END)?;
+        let begin = output[..end].rfind(HANDSHAKE_BEGIN)?;
+        let response = &output[begin.checked_add(HANDSHAKE_BEGIN.len())?..end];
+
+        let mut parts = response.splitn(3, '|');
+        let ki_debugger = parts.next()?.trim().to_string();
+        let tspdbg = parts.next()?.trim().to_string();
+        let capabilities = parts
+            .next()?
+            .split(',')
+            .map(str::trim)
+            .filter(|c| !c.is_empty())
+            .map(ToString::to_string)
+            .collect();
+
+        Some(Self {
+            ki_debugger,
+            tspdbg,
+            capabilities,
+        })
+    }
+
+    /// Check that the loaded debugger matches this application.
+    ///
+    /// # Errors
+    /// Returns [`DebugError::VersionMismatch`] if either script reports a different
+    /// version or if any of the [`KI_DEBUGGER_GLOBAL_FUNCTIONS`] are missing.
+    pub fn verify(&self) -> Result<()> {
+        if self.ki_debugger != VERSION {
+            return Err(DebugError::VersionMismatch {
+                expected: VERSION.to_string(),
+                found: format!("kiDebugger {}", self.ki_debugger),
+            });
+        }
+
+        if self.tspdbg != VERSION {
+            return Err(DebugError::VersionMismatch {
+                expected: VERSION.to_string(),
+                found: format!("tspdbg {}", self.tspdbg),
+            });
+        }
+
+        let missing: Vec<&str> = KI_DEBUGGER_GLOBAL_FUNCTIONS
+            .iter()
+            .filter(|f| !self.capabilities.iter().any(|c| c == *f))
+            .copied()
+            .collect();
+        if !missing.is_empty() {
+            return Err(DebugError::VersionMismatch {
+                expected: VERSION.to_string(),
+                found: format!(
+                    "kiDebugger {} without {}",
+                    self.ki_debugger,
+                    missing.join(", ")
+                ),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::{DebuggerVersion, KI_DEBUGGER_GLOBAL_FUNCTIONS};
+    use crate::{error::DebugError, VERSION};
+
+    fn response(ki_debugger: &str, tspdbg: &str, capabilities: &[&str]) -> String {
+        format!(
+            "TSP>\n<kic-debugger-handshake>{ki_debugger}|{tspdbg}|{}</kic-debugger-handshake>\n",
+            capabilities.join(",")
+        )
+    }
+
+    #[test]
+    fn parse_and_verify_current() {
+        let output = response(VERSION, VERSION, KI_DEBUGGER_GLOBAL_FUNCTIONS);
+        let version = DebuggerVersion::parse(&output).unwrap();
+
+        assert_eq!(version.ki_debugger, VERSION);
+        assert_eq!(
+            version.capabilities.len(),
+            KI_DEBUGGER_GLOBAL_FUNCTIONS.len()
+        );
+        assert!(version.verify().is_ok());
+    }
+
+    #[test]
+    fn stale_debugger_is_rejected() {
+        let output = response("nil", "nil", KI_DEBUGGER_GLOBAL_FUNCTIONS);
+        let version = DebuggerVersion::parse(&output).unwrap();
+
+        assert!(matches!(
+            version.verify(),
+            Err(DebugError::VersionMismatch { found, .. }) if found == "kiDebugger nil"
+        ));
+    }
+
+    #[test]
+    fn missing_capability_is_rejected() {
+        let output = response(VERSION, VERSION, &KI_DEBUGGER_GLOBAL_FUNCTIONS[1..]);
+        let version = DebuggerVersion::parse(&output).unwrap();
+
+        assert!(matches!(
+            version.verify(),
+            Err(DebugError::VersionMismatch { found, .. }) if found.contains("kiClearBreakpoints")
+        ));
+    }
+}
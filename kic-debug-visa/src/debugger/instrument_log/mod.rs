@@ -0,0 +1,164 @@
+//! Control the log of the on-instrument debugger. `kiDebugger` and `tspdbg` log every
+//! remote-comm message to [`LOG_FILE`] if [`LOG_DIR`] exists on the USB drive when
+//! they are loaded, so the log is switched by creating or removing that directory.
+use std::path::PathBuf;
+
+use chrono::Local;
+
+/// The directory on the instrument that enables the log.
+pub const LOG_DIR: &str = "/usb1/tspdbg";
+/// The log file on the instrument.
+pub const LOG_FILE: &str = "/usb1/tspdbg/tspdbg.log";
+
+/// Marks the start of the response printed by the instrument.
+pub const LOG_BEGIN: &str = "<kic-debugger-log>";
+/// Marks the end of the response printed by the instrument.
+pub const LOG_END: &str = "</kic-debugger-log>";
+
+/// What to do with the on-instrument debugger log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogAction {
+    /// Create [`LOG_DIR`] so that the debugger logs from the next session on
+    On,
+    /// Remove [`LOG_FILE`] and [`LOG_DIR`]
+    Off,
+    /// Read [`LOG_FILE`] and clear it
+    Fetch,
+}
+
+impl std::str::FromStr for LogAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "on" => Ok(Self::On),
+            "off" => Ok(Self::Off),
+            "fetch" => Ok(Self::Fetch),
+            _ => Err(format!("'{s}' is not one of on, off or fetch")),
+        }
+    }
+}
+
+/// The state of the log reported by the instrument.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogStatus {
+    /// The log is enabled
+    On,
+    /// The log is disabled
+    Off,
+    /// No USB drive is inserted
+    NoUsb,
+    /// The contents of the log, which was cleared on the instrument
+    Fetched(String),
+}
+
+impl LogAction {
+    /// The TSP command that performs the action and prints the resulting
+    /// [`LogStatus`].
+    ///
+    /// The debugger logs every command it receives, so the markers are put together on
+    /// the instrument and never appear literally in the log. The lines of a fetched log
+    /// are escaped for the same reason.
+    #[must_use]
+    pub fn query(self) -> String {
+        let (begin, end) = (lua_marker(LOG_BEGIN), lua_marker(LOG_END));
+        let action = match self {
+            Self::On => format!(
+                "if not fs.is_dir(\"{LOG_DIR}\") then fs.mkdir(\"{LOG_DIR}\") end print({begin} .. \"on\" .. {end})"
+            ),
+            Self::Off => format!(
+                "if fs.is_file(\"{LOG_FILE}\") then os.remove(\"{LOG_FILE}\") end if fs.is_dir(\"{LOG_DIR}\") then fs.rmdir(\"{LOG_DIR}\") end print({begin} .. \"off\" .. {end})"
+            ),
+            Self::Fetch => format!(
+                "print({begin} .. \"log\") local f = io.open(\"{LOG_FILE}\", \"r\") if f then local l = f:read(\"*l\") while l do print((string.gsub(string.gsub(l, \"&\", \"&amp;\"), \"<\", \"&lt;\"))) l = f:read(\"*l\") end f:close() f = io.open(\"{LOG_FILE}\", \"w\") if f then f:close() end end print({end})"
+            ),
+        };
+        format!(
+            "do if not (fs and fs.is_dir and fs.is_dir(\"/usb1\")) then print({begin} .. \"no-usb\" .. {end}) else {action} end end\n"
+        )
+    }
+}
+
+impl LogStatus {
+    /// Find and parse the response to [`LogAction::query`] in the given instrument
+    /// output.
+    ///
+    /// Returns `None` if the output does not contain a complete response.
+    #[must_use]
+    pub fn parse(output: &str) -> Option<Self> {
+        let end = output.rfind(LOG_END)?;
+        let begin = output[..end].rfind(LOG_BEGIN)?;
+        let response = &output[begin.checked_add(LOG_BEGIN.len())?..end];
+        match response.trim() {
+            "on" => Some(Self::On),
+            "off" => Some(Self::Off),
+            "no-usb" => Some(Self::NoUsb),
+            _ => {
+                let log = response.strip_prefix("log")?;
+                let log = log
+                    .strip_prefix("\r\n")
+                    .or_else(|| log.strip_prefix('\n'))
+                    .unwrap_or(log);
+                Some(Self::Fetched(
+                    log.replace("\r\n", "\n")
+                        .replace("&lt;", "<")
+                        .replace("&amp;", "&"),
+                ))
+            }
+        }
+    }
+}
+
+/// A Lua expression that evaluates to `marker` without containing it.
+fn lua_marker(marker: &str) -> String {
+    let (head, tail) = marker.split_at(marker.len() / 2);
+    format!("\"{head}\" .. \"{tail}\"")
+}
+
+/// Where a fetched log is saved.
+#[must_use]
+pub fn local_copy_path() -> PathBuf {
+    std::env::temp_dir().join("kic-debug").join(format!(
+        "tspdbg-{}.log",
+        Local::now().format("%Y%m%d-%H%M%S")
+    ))
+}
+
+#[cfg(test)]
+mod unit {
+    use super::{LogAction, LogStatus, LOG_BEGIN, LOG_END};
+
+    #[test]
+    fn parse_status() {
+        assert_eq!(
+            LogStatus::parse(&format!("{LOG_BEGIN}on{LOG_END}\n")),
+            Some(LogStatus::On)
+        );
+        assert_eq!(
+            LogStatus::parse(&format!("{LOG_BEGIN}no-usb{LOG_END}")),
+            Some(LogStatus::NoUsb)
+        );
+        assert_eq!(
+            LogStatus::parse(&format!(
+                "{LOG_BEGIN}log\r\n2024-01-02 10:00:00\tkiRun\t\r\n{LOG_END}\r\n"
+            )),
+            Some(LogStatus::Fetched(
+                "2024-01-02 10:00:00\tkiRun\t\n".to_string()
+            ))
+        );
+        assert_eq!(LogStatus::parse(LOG_BEGIN), None);
+    }
+
+    #[test]
+    fn fetched_log_may_contain_the_markers() {
+        let query = LogAction::Fetch.query();
+        assert!(!query.contains(LOG_BEGIN) && !query.contains(LOG_END));
+
+        let logged = format!("command: print(\"{LOG_BEGIN}on{LOG_END}\") & more");
+        let escaped = logged.replace('&', "&amp;").replace('<', "&lt;");
+        assert_eq!(
+            LogStatus::parse(&format!("{LOG_BEGIN}log\r\n{escaped}\r\n{LOG_END}\r\n")),
+            Some(LogStatus::Fetched(format!("{logged}\n")))
+        );
+    }
+}
@@ -0,0 +1,367 @@
+//! Split the instrument output into script output, debugger events and instrument
+//! errors so each can be routed to its own sink.
+//!
+//! Every kind is written to the console unchanged unless it is given its own sink,
+//! since the debugger front end parses the debugger events from there. Each kind of
+//! output can also be teed to a file.
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use chrono::Local;
+
+use crate::error::Result;
+
+/// The start tag of the frames printed by the on-instrument debugger.
+pub const FRAME_START: &str = "<tspdbg-AA4E9540-A46C-4671-81D7-4FE69A9B6DC4>";
+/// The end tag of the frames printed by the on-instrument debugger.
+pub const FRAME_END: &str = "</tspdbg-AA4E9540-A46C-4671-81D7-4FE69A9B6DC4>";
+
+/// The debugger frames that report errors.
+const ERROR_FRAMES: &[&str] = &[
+    "execution-failed",
+    "command-execution-failed",
+    "command-compilation-failed",
+    "instrument-error",
+];
+
+/// The kinds of output the instrument produces during a debug session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputKind {
+    /// Output printed by the debuggee
+    Script,
+    /// Debugger frames and prompts
+    Event,
+    /// Errors reported by the instrument
+    Error,
+}
+
+impl OutputKind {
+    /// Classify a single line of output that is not part of a debugger frame. Only the
+    /// prompts and the errors the instrument reports for a command it can't run are
+    /// told apart from the output of the debuggee.
+    #[must_use]
+    pub fn of_line(line: &str) -> Self {
+        let line = line.trim();
+        if matches!(line, "TSP>" | "TSP?" | ">>>>") {
+            Self::Event
+        } else if line.starts_with("TSP Syntax error") || line.starts_with("TSP Runtime error") {
+            Self::Error
+        } else {
+            Self::Script
+        }
+    }
+
+    /// Classify a debugger frame by its element.
+    #[must_use]
+    pub fn of_frame(frame: &str) -> Self {
+        let element = frame
+            .strip_prefix(FRAME_START)
+            .map(str::trim_start)
+            .and_then(|f| f.strip_prefix('<'))
+            .and_then(|f| f.split([' ', '/', '>']).next())
+            .unwrap_or_default();
+        if ERROR_FRAMES.contains(&element) {
+            Self::Error
+        } else {
+            Self::Event
+        }
+    }
+
+    const fn index(self) -> usize {
+        match self {
+            Self::Script => 0,
+            Self::Event => 1,
+            Self::Error => 2,
+        }
+    }
+}
+
+/// How much of the latest instrument output is kept for crash reports, in bytes.
+const TAIL_LEN: usize = 64 * 1024;
+
+/// Splits the instrument output into [`OutputKind`]s and writes each to its sinks.
+pub struct Demultiplexer {
+    pending: String,
+    console: Box<dyn Write + Send>,
+    /// The sink of each kind, the console if `None`
+    sinks: [Option<Box<dyn Write + Send>>; 3],
+    tees: [Option<BufWriter<File>>; 3],
+    /// The latest instrument output, unchanged
+    tail: String,
+}
+
+impl Default for Demultiplexer {
+    fn default() -> Self {
+        Self::new(Box::new(std::io::stdout()))
+    }
+}
+
+impl Demultiplexer {
+    /// Create a demultiplexer that writes all output, unchanged, to `console`.
+    #[must_use]
+    pub fn new(console: Box<dyn Write + Send>) -> Self {
+        Self {
+            pending: String::new(),
+            console,
+            sinks: [None, None, None],
+            tees: [None, None, None],
+            tail: String::new(),
+        }
+    }
+
+    /// Write all output to `console` instead.
+    pub fn set_console(&mut self, console: Box<dyn Write + Send>) {
+        self.console = console;
+    }
+
+    /// Write the output of the given `kind` to `sink` instead of the console.
+    pub fn set_sink(&mut self, kind: OutputKind, sink: Box<dyn Write + Send>) {
+        self.sinks[kind.index()] = Some(sink);
+    }
+
+    /// Write debugger messages that aren't instrument output to the console only.
+    ///
+    /// # Errors
+    /// Returns an IO error if the console can't be written.
+    pub fn write_console(&mut self, text: &str) -> Result<()> {
+        self.console.write_all(text.as_bytes())?;
+        self.console.flush()?;
+        Ok(())
+    }
+
+    /// Also write the output of the given `kind` to the file at `path`. Script output
+    /// is timestamped in the file.
+    ///
+    /// # Errors
+    /// Returns an IO error if the file can't be created.
+    pub fn tee(&mut self, kind: OutputKind, path: &Path) -> Result<()> {
+        self.tees[kind.index()] = Some(BufWriter::new(File::create(path)?));
+        Ok(())
+    }
+
+    /// Route the output read from the instrument. Incomplete lines and debugger frames
+    /// are held back until the rest arrives or [`Self::flush`] is called.
+    ///
+    /// # Returns
+    /// The debugger frames that were routed.
+    ///
+    /// # Errors
+    /// Returns an IO error if a sink can't be written.
+    pub fn push(&mut self, output: &str) -> Result<Vec<String>> {
+        self.tail.push_str(output);
+        if self.tail.len() > TAIL_LEN {
+            let mut cut = self.tail.len() - TAIL_LEN;
+            while !self.tail.is_char_boundary(cut) {
+                cut += 1;
+            }
+            self.tail.drain(..cut);
+        }
+        self.pending.push_str(output);
+        let mut frames = Vec::new();
+        for (kind, text) in self.split() {
+            self.write(kind, &text)?;
+            if text.starts_with(FRAME_START) {
+                frames.push(text);
+            }
+        }
+        Ok(frames)
+    }
+
+    /// Route anything that is still held back, e.g. a line without a trailing newline.
+    ///
+    /// # Errors
+    /// Returns an IO error if a sink can't be written.
+    pub fn flush(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let text = std::mem::take(&mut self.pending);
+        let kind = if text.starts_with(FRAME_START) {
+            OutputKind::of_frame(&text)
+        } else {
+            OutputKind::of_line(&text)
+        };
+        self.write(kind, &text)
+    }
+
+    /// The latest output read from the instrument, up to 64 KiB.
+    #[must_use]
+    pub fn tail(&self) -> &str {
+        &self.tail
+    }
+
+    /// Whether some output is held back.
+    #[must_use]
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Take the complete lines and frames from the pending output.
+    fn split(&mut self) -> Vec<(OutputKind, String)> {
+        let mut chunks = Vec::new();
+        loop {
+            if self.pending.starts_with(FRAME_START) {
+                let Some(end) = self.pending.find(FRAME_END) else {
+                    break;
+                };
+                let mut end = end + FRAME_END.len();
+                if self.pending[end..].starts_with('\n') {
+                    end += 1;
+                }
+                let frame: String = self.pending.drain(..end).collect();
+                chunks.push((OutputKind::of_frame(&frame), frame));
+                continue;
+            }
+
+            let line_end = self.pending.find('\n').map(|i| i + 1);
+            let frame_start = self.pending.find(FRAME_START);
+            let end = match (line_end, frame_start) {
+                (Some(l), Some(f)) => l.min(f),
+                (Some(l), None) => l,
+                (None, Some(f)) => f,
+                (None, None) => break,
+            };
+            let line: String = self.pending.drain(..end).collect();
+            chunks.push((OutputKind::of_line(&line), line));
+        }
+        chunks
+    }
+
+    fn write(&mut self, kind: OutputKind, text: &str) -> Result<()> {
+        let sink = self.sinks[kind.index()]
+            .as_mut()
+            .unwrap_or(&mut self.console);
+        sink.write_all(text.as_bytes())?;
+        sink.flush()?;
+
+        if let Some(tee) = &mut self.tees[kind.index()] {
+            if kind == OutputKind::Script {
+                let timestamp = Local::now().format("%Y-%m-%dT%H:%M:%S%.3f");
+                for line in text.lines() {
+                    writeln!(tee, "[{timestamp}] {line}")?;
+                }
+            } else {
+                tee.write_all(text.as_bytes())?;
+            }
+            tee.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Split the attributes of an element like `name='x' value='1' />`. The `tableData`
+/// attribute holds unescaped JSON, so it is taken up to the end of the element. The
+/// values are left escaped, see [`unescape`].
+#[must_use]
+pub fn parse_attributes(attributes: &str) -> Vec<(String, String)> {
+    let attributes = attributes
+        .trim_end_matches('>')
+        .trim_end_matches('/')
+        .trim_end();
+    let (attributes, table_data) = match attributes.split_once(" tableData='") {
+        Some((rest, table_data)) => (rest, table_data.strip_suffix('\'')),
+        None => (attributes, None),
+    };
+
+    let mut parsed = Vec::new();
+    let mut rest = attributes.trim();
+    while let Some((name, value)) = rest.split_once("='") {
+        let Some((value, tail)) = value.split_once('\'') else {
+            break;
+        };
+        parsed.push((name.trim().to_string(), value.to_string()));
+        rest = tail.trim_start();
+    }
+    if let Some(table_data) = table_data {
+        parsed.push(("tableData".to_string(), table_data.to_string()));
+    }
+    parsed
+}
+
+/// Undo the escaping of the on-instrument debugger, which escapes XML special
+/// characters as entities and control characters as `&#x24NN;`.
+#[must_use]
+pub fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..end];
+        let c = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x24")
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .map(char::from),
+        };
+        match c {
+            Some(c) => {
+                unescaped.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+#[cfg(test)]
+mod unit {
+    use super::{Demultiplexer, OutputKind, FRAME_END, FRAME_START};
+
+    #[test]
+    fn classify_lines() {
+        assert_eq!(OutputKind::of_line("hello\n"), OutputKind::Script);
+        assert_eq!(
+            OutputKind::of_line("TSP Syntax error at line 1: unexpected symbol\n"),
+            OutputKind::Error
+        );
+        assert_eq!(OutputKind::of_line("TSP>\n"), OutputKind::Event);
+        assert_eq!(OutputKind::of_line("100,0.5\n"), OutputKind::Script);
+        assert_eq!(OutputKind::of_line("-1,2\n"), OutputKind::Script);
+        assert_eq!(
+            OutputKind::of_frame(&format!(
+                "{FRAME_START}<execution-failed error='x' />{FRAME_END}"
+            )),
+            OutputKind::Error
+        );
+        assert_eq!(
+            OutputKind::of_frame(&format!("{FRAME_START}<debug-prompt />{FRAME_END}")),
+            OutputKind::Event
+        );
+    }
+
+    #[test]
+    fn frames_split_across_reads() {
+        let mut demux = Demultiplexer::new(Box::new(std::io::sink()));
+        demux.pending = format!("out\n{FRAME_START}<stacks>\n");
+        assert_eq!(demux.split(), [(OutputKind::Script, "out\n".to_string())]);
+
+        demux
+            .pending
+            .push_str(&format!("</stacks>{FRAME_END}\npartial"));
+        assert_eq!(
+            demux.split(),
+            [(
+                OutputKind::Event,
+                format!("{FRAME_START}<stacks>\n</stacks>{FRAME_END}\n")
+            )]
+        );
+        assert!(demux.has_pending());
+    }
+}
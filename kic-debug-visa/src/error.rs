@@ -56,6 +56,59 @@ pub enum DebugError {
         source: clap::error::Error,
     },
 
+    /// An embedded resource could not be decrypted.
+    #[error("unable to decrypt resource: {source}")]
+    ResourceDecryptError {
+        /// The reason decryption failed
+        #[from]
+        source: crate::resources::DecryptError,
+    },
+
+    /// The debugger loaded on the instrument is not the one that was just uploaded,
+    /// most likely because a stale copy is saved on the instrument.
+    #[error("debugger version mismatch: expected {expected}, found {found}. Delete any kiDebugger or tspdbg script saved on the instrument and try again.")]
+    VersionMismatch {
+        /// The version of this application
+        expected: String,
+        /// The version and capabilities reported by the instrument
+        found: String,
+    },
+
+    /// The debugger could not be removed from the instrument completely.
+    #[error("debugger cleanup could not be verified: {details}. Run `cleanup` on the instrument to try again.")]
+    CleanupIncomplete {
+        /// What is left on the instrument, or why it couldn't be checked
+        details: String,
+    },
+
+    /// The connection to the instrument was lost and could not be re-established.
+    #[error("connection to the instrument was lost and could not be re-established after {attempts} attempts: {source}")]
+    ConnectionLost {
+        /// The number of reconnect attempts that were made
+        attempts: usize,
+        /// The error from the last reconnect attempt
+        source: kic_lib::InstrumentError,
+    },
+
+    /// The debugger could not set a variable, e.g. because it doesn't exist in the
+    /// given scope or the value could not be evaluated.
+    #[error("variable '{name}' could not be set")]
+    VariableNotSet {
+        /// The name of the variable, with table fields separated by `.`
+        name: String,
+    },
+
+    /// The instrument lacks what the debugger needs, so nothing was uploaded.
+    #[error("the {model} with firmware {firmware} can't be debugged: {reason}")]
+    UnsupportedFirmware {
+        /// The model of the instrument
+        model: String,
+        /// The firmware revision of the instrument
+        firmware: String,
+        /// The missing primitives, or that the instrument didn't respond
+        reason: String,
+    },
+
     /// Some other error
     #[error("{0}")]
     Other(String),
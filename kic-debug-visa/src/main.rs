@@ -1,13 +1,26 @@
+use anyhow::Context;
 use chrono::Utc;
-use clap::{command, value_parser, Arg, ArgMatches, Command};
+use clap::{command, value_parser, Arg, ArgAction, ArgMatches, Command};
 use colored::Colorize;
-use kic_debug_visa::debugger::Debugger;
+use kic_debug_visa::debugger::{
+    frontend::{StdinSource, TcpFrontEnd},
+    output::OutputKind,
+    rack::Rack,
+    reconnect::ReconnectPolicy,
+    Debugger,
+};
+use kic_debug_visa::error::DebugError;
+use kic_debug_visa::test_runner;
 use kic_lib::{
     instrument::{authenticate::Authentication, CmdLanguage, Instrument, State},
     model::connect_to,
     ConnectionInfo,
 };
+#[cfg(feature = "simulator")]
+use kic_lib::{interface::NonBlock, model::simulator};
 use std::io::{stdin, ErrorKind};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::process::exit;
 use std::thread;
 use std::time::Duration;
@@ -54,8 +67,54 @@ fn main() -> anyhow::Result<()> {
         .subcommand({
             let connect_command = Command::new("connect")
                 .about("Connect to an instrument over one of the provided interfaces");
-            add_connection_subcommands(connect_command)
-        });
+            add_session_args(add_connection_subcommands(connect_command)).arg(
+                Arg::new("also")
+                    .help("Also debug on this instrument, with one console for all of them. Can be given several times")
+                    .required(false)
+                    .long("also")
+                    .action(ArgAction::Append)
+                    .value_parser(value_parser!(ConnectionInfo)),
+            )
+        })
+        .subcommand({
+            let cleanup_command = Command::new("cleanup")
+                .about(
+                    "Remove debugger scripts and globals left on an instrument by a previous session",
+                )
+                .arg(
+                    Arg::new("caches")
+                        .help("Also remove the debugger scripts saved by --cache-debugger")
+                        .required(false)
+                        .long("caches")
+                        .action(ArgAction::SetTrue),
+                );
+            add_connection_subcommands(cleanup_command)
+        })
+        .subcommand(add_test_args(add_connection_subcommands(
+            Command::new("test")
+                .about("Run the `test_*` functions of TSP scripts and report the results")
+                .arg(
+                    Arg::new("path")
+                        .help("The script to test, or a directory with the scripts to test")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        )));
+    #[cfg(feature = "simulator")]
+    let cmd =
+        cmd.subcommand(
+            add_session_args(Command::new("simulate").about(
+                "Debug with a TSP simulator running on this computer instead of an instrument",
+            ))
+            .arg(
+                Arg::new("instruments")
+                    .help("Debug on this many simulators, with one console for all of them")
+                    .required(false)
+                    .long("instruments")
+                    .default_value("1")
+                    .value_parser(value_parser!(u16).range(1..)),
+            ),
+        );
     let matches = cmd.clone().get_matches();
 
     if let Some(("print-description", _)) = matches.subcommand() {
@@ -65,12 +124,98 @@ fn main() -> anyhow::Result<()> {
 
     let mut debugger: Debugger = match matches.subcommand() {
         Some(("connect", sub_matches)) => {
+            let also: Vec<ConnectionInfo> = sub_matches
+                .get_many::<ConnectionInfo>("also")
+                .map(|c| c.cloned().collect())
+                .unwrap_or_default();
+            if !also.is_empty() {
+                let mut rack = Rack::new();
+                for (n, conn) in sub_matches
+                    .get_one::<ConnectionInfo>("addr")
+                    .into_iter()
+                    .chain(&also)
+                    .enumerate()
+                {
+                    let (conn, args, name) = (conn.clone(), sub_matches.clone(), conn.to_string());
+                    let front_end = bind_member_front_end(sub_matches, &name, n)?;
+                    rack.add(
+                        name.clone(),
+                        Box::new(move || {
+                            let mut debugger = debug_session(&conn, &args)
+                                .map_err(|e| DebugError::Other(e.to_string()))?;
+                            configure_session(&mut debugger, &args, None, Some(&name))
+                                .map_err(|e| DebugError::Other(e.to_string()))?;
+                            Ok(debugger)
+                        }),
+                        front_end,
+                    );
+                }
+                return start_rack(rack);
+            }
+            // Bind before connecting so that a bad address doesn't leave a connection
+            // to the instrument behind.
+            let front_end = bind_front_end(sub_matches, 0)?;
+            let conn = sub_matches
+                .get_one::<ConnectionInfo>("addr")
+                .expect("clap should require an address");
+            let mut debugger = debug_session(conn, sub_matches)?;
+            configure_session(&mut debugger, sub_matches, front_end, None)?;
+            debugger
+        }
+        #[cfg(feature = "simulator")]
+        Some(("simulate", sub_matches)) => {
+            let instruments = sub_matches
+                .get_one::<u16>("instruments")
+                .copied()
+                .unwrap_or(1);
+            if instruments > 1 {
+                let mut rack = Rack::new();
+                for n in 0..instruments {
+                    let (args, name) = (sub_matches.clone(), format!("sim{}", n + 1));
+                    let front_end = bind_member_front_end(sub_matches, &name, n.into())?;
+                    rack.add(
+                        name.clone(),
+                        Box::new(move || {
+                            let mut debugger = simulator_session()
+                                .map_err(|e| DebugError::Other(e.to_string()))?;
+                            configure_session(&mut debugger, &args, None, Some(&name))
+                                .map_err(|e| DebugError::Other(e.to_string()))?;
+                            Ok(debugger)
+                        }),
+                        front_end,
+                    );
+                }
+                return start_rack(rack);
+            }
+            let front_end = bind_front_end(sub_matches, 0)?;
+            let mut debugger = simulator_session()?;
+            configure_session(&mut debugger, sub_matches, front_end, None)?;
+            debugger
+        }
+        Some(("cleanup", sub_matches)) => {
             let mut instrument = connect(sub_matches).map_err(|e| {
                 eprintln!("Failed to connect to instrument: {e}");
                 e
             })?;
             clear_output_queue(&mut instrument, 5000, Duration::from_millis(1))?;
-            Debugger::new(instrument)
+            let mut debugger = Debugger::new(instrument);
+            let removed = debugger.cleanup(sub_matches.get_flag("caches"))?;
+            if removed.is_empty() {
+                println!("No debugger leftovers found on the instrument.");
+            } else {
+                println!("Removed from the instrument: {}", removed.join(", "));
+            }
+            return Ok(());
+        }
+        Some(("test", sub_matches)) => {
+            let mut instrument = test_instrument(sub_matches)?;
+            clear_output_queue(&mut instrument, 5000, Duration::from_millis(1))?;
+            let passed = run_tests(&mut *instrument, sub_matches)?;
+            drop(instrument);
+            if !passed {
+                exit(1);
+            }
+            return Ok(());
         }
         _ => unreachable!(),
     };
@@ -78,6 +223,193 @@ fn main() -> anyhow::Result<()> {
     Ok(debugger.start()?)
 }
 
+/// Run the tests given on the command line and report the results.
+///
+/// # Returns
+/// Whether all tests passed.
+fn run_tests(instrument: &mut dyn Instrument, args: &ArgMatches) -> anyhow::Result<bool> {
+    let Some(path) = args.get_one::<PathBuf>("path") else {
+        return Err(KicError::ArgParseError {
+            details: "no script to test was given".to_string(),
+        }
+        .into());
+    };
+    let timeout = Duration::from_secs(args.get_one::<u64>("timeout").copied().unwrap_or(600));
+
+    let mut suites = Vec::new();
+    for script in test_runner::discover(path)? {
+        println!("Testing {}", script.display());
+        suites.push(test_runner::run_file(instrument, &script, timeout)?);
+    }
+    let summary = test_runner::summary(&suites);
+    let passed = suites.iter().all(|s| s.failures() + s.errors() == 0);
+    if passed {
+        println!("{}", summary.green());
+    } else {
+        println!("{}", summary.red());
+    }
+    if let Some(junit) = args.get_one::<PathBuf>("junit") {
+        std::fs::write(junit, test_runner::junit(&suites))
+            .with_context(|| format!("unable to write {}", junit.display()))?;
+    }
+    Ok(passed)
+}
+
+/// Connect to `conn` and set up a debugger for it.
+fn debug_session(conn: &ConnectionInfo, args: &ArgMatches) -> anyhow::Result<Debugger> {
+    let (mut instrument, auth) = connect_to_instrument(conn, args).map_err(|e| {
+        eprintln!("Failed to connect to instrument: {e}");
+        e
+    })?;
+    clear_output_queue(&mut instrument, 5000, Duration::from_millis(1))?;
+    let mut debugger = Debugger::new(instrument);
+    // Prompting for credentials would compete with the debugger for stdin, so
+    // only reconnect automatically if no prompt is needed.
+    if !matches!(
+        auth,
+        Authentication::Prompt | Authentication::PromptPartial { .. }
+    ) {
+        debugger.set_reconnect_policy(ReconnectPolicy::new(conn.clone(), auth));
+    }
+    Ok(debugger)
+}
+
+/// The instrument to run the tests on, a TSP simulator if `--simulate` is given.
+fn test_instrument(args: &ArgMatches) -> anyhow::Result<Box<dyn Instrument>> {
+    #[cfg(feature = "simulator")]
+    if args.get_flag("simulate") {
+        return Ok(Box::new(simulator::Instrument::new()?));
+    }
+    connect(args).map_err(|e| {
+        eprintln!("Failed to connect to instrument: {e}");
+        e
+    })
+}
+
+/// Start a TSP simulator and set up a debugger for it.
+#[cfg(feature = "simulator")]
+fn simulator_session() -> anyhow::Result<Debugger> {
+    let mut instrument = simulator::Instrument::new()?;
+    instrument.set_nonblocking(true)?;
+    Ok(Debugger::new(Box::new(instrument)))
+}
+
+/// Debug on all instruments of `rack` from one console on stdin and stdout.
+fn start_rack(rack: Rack) -> anyhow::Result<()> {
+    Ok(rack.start(Box::new(StdinSource), Box::new(std::io::stdout()))?)
+}
+
+/// Bind the front end socket of the session at index `n` of several, `n` ports after
+/// the one given with `--listen`, if any.
+fn bind_member_front_end(
+    args: &ArgMatches,
+    name: &str,
+    n: usize,
+) -> anyhow::Result<Option<TcpFrontEnd>> {
+    let front_end = bind_front_end(args, n)?;
+    if let Some(front_end) = &front_end {
+        eprintln!(
+            "Waiting for a front end for {name} on {}",
+            front_end.local_addr()?
+        );
+    }
+    Ok(front_end)
+}
+
+/// Bind the front end socket given with `--listen`, if any, with its port moved up by
+/// `offset` unless it is chosen by the system.
+fn bind_front_end(args: &ArgMatches, offset: usize) -> anyhow::Result<Option<TcpFrontEnd>> {
+    let addr = args.get_one::<SocketAddr>("listen").map(|addr| {
+        let port = match addr.port() {
+            0 => Ok(0),
+            port => u16::try_from(offset)
+                .ok()
+                .and_then(|offset| port.checked_add(offset))
+                .ok_or(KicError::ArgParseError {
+                    details: format!(
+                        "there are not enough ports after {addr} for every instrument"
+                    ),
+                }),
+        };
+        port.map(|port| SocketAddr::new(addr.ip(), port))
+    });
+    match addr.transpose()? {
+        Some(addr) if !addr.ip().is_loopback() => Err(KicError::ArgParseError {
+            details: format!(
+                "{addr} is not a local address, only loopback addresses can be used with --listen"
+            ),
+        }
+        .into()),
+        Some(addr) => Ok(Some(TcpFrontEnd::bind(addr)?)),
+        None => Ok(None),
+    }
+}
+
+/// Apply the session arguments given on the command line to `debugger`. The log files
+/// of a session that is one of several get the `member` name appended.
+fn configure_session(
+    debugger: &mut Debugger,
+    args: &ArgMatches,
+    front_end: Option<TcpFrontEnd>,
+    member: Option<&str>,
+) -> anyhow::Result<()> {
+    debugger.set_cache_scripts(args.get_flag("cache-debugger"));
+    debugger.set_minify_scripts(args.get_flag("minify"));
+    debugger.set_highlight_changes(args.get_flag("highlight-changes"));
+    debugger.set_stop_on_exit(args.get_flag("stop-on-exit"));
+    debugger.set_break_on_instrument_error(args.get_flag("break-on-error"));
+    debugger.set_pausable(args.get_flag("pausable"));
+    if args.get_flag("errors-to-stderr") {
+        debugger.set_output_sink(OutputKind::Error, Box::new(std::io::stderr()));
+    }
+    if let Some(path) = args.get_one::<PathBuf>("mocks") {
+        debugger.set_mocks_file(path.clone());
+    }
+    if let Some(dir) = args.get_one::<PathBuf>("crash-dir") {
+        debugger.set_crash_dir(dir.clone());
+    }
+    if let Some(front_end) = front_end {
+        eprintln!("Waiting for a front end on {}", front_end.local_addr()?);
+        let output = front_end.output();
+        debugger.set_front_end(Box::new(front_end), Box::new(output));
+    }
+    for (arg, kind) in [
+        ("script-log", OutputKind::Script),
+        ("event-log", OutputKind::Event),
+        ("error-log", OutputKind::Error),
+    ] {
+        if let Some(path) = args.get_one::<PathBuf>(arg) {
+            match member {
+                Some(member) => debugger.tee_output(kind, &member_log_path(path, member))?,
+                None => debugger.tee_output(kind, path)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The log file of one of several sessions, e.g. `events-192.168.0.2.log` for
+/// `events.log`.
+fn member_log_path(path: &Path, member: &str) -> PathBuf {
+    let member: String = member
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{stem}-{member}.{}", ext.to_string_lossy()),
+        None => format!("{stem}-{member}"),
+    };
+    path.with_file_name(name)
+}
+
+/// Connect to the instrument given on the command line.
 fn connect(args: &ArgMatches) -> anyhow::Result<Box<dyn Instrument>> {
     let Some(conn) = args.get_one::<ConnectionInfo>("addr") else {
         error!("No IP address or VISA resource string given");
@@ -91,9 +423,21 @@ fn connect(args: &ArgMatches) -> anyhow::Result<Box<dyn Instrument>> {
         }
         .into());
     };
+    let (instrument, _) = connect_to_instrument(conn, args)?;
+    Ok(instrument)
+}
+
+/// Connect to `conn` with the authentication given on the command line.
+///
+/// # Returns
+/// The instrument along with the authentication used.
+fn connect_to_instrument(
+    conn: &ConnectionInfo,
+    args: &ArgMatches,
+) -> anyhow::Result<(Box<dyn Instrument>, Authentication)> {
     let auth = auth_type(conn, args);
 
-    let mut instrument: Box<dyn Instrument> = match get_instrument(conn, auth) {
+    let mut instrument: Box<dyn Instrument> = match get_instrument(conn, auth.clone()) {
         Ok(i) => i,
         Err(e) => {
             error!("Error connecting to async instrument: {e}");
@@ -106,7 +450,7 @@ fn connect(args: &ArgMatches) -> anyhow::Result<Box<dyn Instrument>> {
         return Err(e);
     }
 
-    Ok(instrument)
+    Ok((instrument, auth))
 }
 
 fn get_instrument_access(inst: &mut Box<dyn Instrument>) -> anyhow::Result<()> {
@@ -158,6 +502,133 @@ fn get_instrument_access(inst: &mut Box<dyn Instrument>) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Add the arguments that configure the debug session.
+fn add_session_args(command: Command) -> Command {
+    command
+        .arg(
+            Arg::new("cache-debugger")
+                .help("Save the debugger scripts on the instrument and only upload them again when they change")
+                .required(false)
+                .long("cache-debugger")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("minify")
+                .help("Strip comments and whitespace from the debugged script before uploading it, keeping line numbers")
+                .required(false)
+                .long("minify")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("highlight-changes")
+                .help("Report the variables and watch values that changed since the previous stop")
+                .required(false)
+                .long("highlight-changes")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("stop-on-exit")
+                .help("Keep the session paused after the last line of the debugged script to inspect its final state")
+                .required(false)
+                .long("stop-on-exit")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("break-on-error")
+                .help("Pause the debugged script when the instrument error queue is not empty and show the queued errors")
+                .required(false)
+                .long("break-on-error")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("pausable")
+                .help("Let `.debug pause` pause the running script, at a small cost on every line it runs")
+                .required(false)
+                .long("pausable")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("mocks")
+                .help("Run the Lua mocks in this file before the debugged script, e.g. `mock(\"delay\", function() end)`")
+                .required(false)
+                .long("mocks")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("crash-dir")
+                .help("Write a crash report to this directory when the debugged script fails or the connection to the instrument is lost")
+                .required(false)
+                .long("crash-dir")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("listen")
+                .help("Accept a front end on this local address (e.g. 127.0.0.1:5025) instead of using stdin and stdout")
+                .required(false)
+                .long("listen")
+                .value_parser(value_parser!(SocketAddr)),
+        )
+        .arg(
+            Arg::new("errors-to-stderr")
+                .help("Write the errors reported by the instrument and the debugger to stderr instead of stdout")
+                .required(false)
+                .long("errors-to-stderr")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("script-log")
+                .help("Also write the output of the debugged script to this file, with timestamps")
+                .required(false)
+                .long("script-log")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("event-log")
+                .help("Also write the debugger events to this file")
+                .required(false)
+                .long("event-log")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("error-log")
+                .help("Also write the errors reported by the instrument to this file")
+                .required(false)
+                .long("error-log")
+                .value_parser(value_parser!(PathBuf)),
+        )
+}
+
+fn add_test_args(command: Command) -> Command {
+    #[cfg(feature = "simulator")]
+    let command = command
+        .mut_arg("addr", |a| {
+            a.required(false).required_unless_present("simulate")
+        })
+        .arg(
+            Arg::new("simulate")
+                .help("Run the tests on a TSP simulator on this computer instead of an instrument")
+                .required(false)
+                .long("simulate")
+                .action(ArgAction::SetTrue),
+        );
+    command
+        .arg(
+            Arg::new("junit")
+                .help("Write the results as JUnit XML to this file")
+                .required(false)
+                .long("junit")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("timeout")
+                .help("Abort the tests of a script that don't finish within this many seconds")
+                .required(false)
+                .long("timeout")
+                .default_value("600")
+                .value_parser(value_parser!(u64)),
+        )
+}
+
 fn add_connection_subcommands(command: impl Into<Command>) -> Command {
     let mut command: Command = command.into();
 
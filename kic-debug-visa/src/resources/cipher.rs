@@ -0,0 +1,101 @@
+//! The authenticated encryption used for embedded resources: AES-256 in counter mode
+//! with an HMAC-SHA256 tag over the nonce and ciphertext (encrypt-then-MAC).
+//!
+//! This file is shared with the build script, which encrypts the resources at build
+//! time, so it must not depend on anything else in this crate.
+
+use aes::{
+    cipher::{generic_array::GenericArray, BlockEncrypt, KeyInit},
+    Aes256,
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use thiserror::Error;
+
+/// The length of a resource key: an AES-256 key followed by an HMAC-SHA256 key.
+pub const KEY_LEN: usize = 64;
+/// The length of the nonce that is prepended to every sealed resource.
+pub const NONCE_LEN: usize = 16;
+/// The length of the authentication tag that is appended to every sealed resource.
+pub const TAG_LEN: usize = 32;
+
+const CIPHER_KEY_LEN: usize = 32;
+
+/// An error that occurs when an encrypted resource can't be opened.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecryptError {
+    /// The resource is too short to hold a nonce and an authentication tag.
+    #[error("encrypted resource is truncated")]
+    Truncated,
+
+    /// The authentication tag didn't match, so the resource was modified or was sealed
+    /// with a different key.
+    #[error("encrypted resource failed authentication and may have been tampered with")]
+    Tampered,
+}
+
+/// Seal `plaintext` with `key`, returning `nonce || ciphertext || tag`.
+#[must_use]
+pub fn seal(key: &[u8; KEY_LEN], nonce: &[u8; NONCE_LEN], plaintext: &[u8]) -> Vec<u8> {
+    let (cipher_key, mac_key) = key.split_at(CIPHER_KEY_LEN);
+
+    let mut ciphertext = plaintext.to_vec();
+    apply_keystream(cipher_key, nonce, &mut ciphertext);
+    let tag = authenticator(mac_key, nonce, &ciphertext)
+        .finalize()
+        .into_bytes();
+
+    let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len() + TAG_LEN);
+    sealed.extend_from_slice(nonce);
+    sealed.extend_from_slice(&ciphertext);
+    sealed.extend_from_slice(&tag);
+    sealed
+}
+
+/// Authenticate and decrypt a resource produced by [`seal`].
+///
+/// # Errors
+/// Returns [`DecryptError::Truncated`] if `sealed` is too short and
+/// [`DecryptError::Tampered`] if the authentication tag doesn't match.
+pub fn open(key: &[u8; KEY_LEN], sealed: &[u8]) -> Result<Vec<u8>, DecryptError> {
+    if sealed.len() < NONCE_LEN + TAG_LEN {
+        return Err(DecryptError::Truncated);
+    }
+    let (cipher_key, mac_key) = key.split_at(CIPHER_KEY_LEN);
+    let (nonce, rest) = sealed.split_at(NONCE_LEN);
+    let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+
+    authenticator(mac_key, nonce, ciphertext)
+        .verify_slice(tag)
+        .map_err(|_| DecryptError::Tampered)?;
+
+    let mut plaintext = ciphertext.to_vec();
+    apply_keystream(cipher_key, nonce, &mut plaintext);
+    Ok(plaintext)
+}
+
+fn authenticator(mac_key: &[u8], nonce: &[u8], ciphertext: &[u8]) -> Hmac<Sha256> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(mac_key)
+        .expect("HMAC should accept a key of any length");
+    mac.update(nonce);
+    mac.update(ciphertext);
+    mac
+}
+
+/// XOR `data` with the AES-256-CTR keystream that starts at the counter `nonce`.
+fn apply_keystream(cipher_key: &[u8], nonce: &[u8], data: &mut [u8]) {
+    let cipher = Aes256::new(GenericArray::from_slice(cipher_key));
+    let mut counter = u128::from_be_bytes(
+        nonce
+            .try_into()
+            .expect("nonce should be exactly one AES block"),
+    );
+    for chunk in data.chunks_mut(16) {
+        let mut block = GenericArray::from(counter.to_be_bytes());
+        cipher.encrypt_block(&mut block);
+        for (byte, key) in chunk.iter_mut().zip(block.iter()) {
+            *byte ^= key;
+        }
+        counter = counter.wrapping_add(1);
+    }
+}
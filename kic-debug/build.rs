@@ -0,0 +1,71 @@
+//! Encrypt the debugger scripts so they aren't embedded in the binary as plain text.
+//!
+//! The key is derived from the package name and version, or from `KIC_RESOURCE_SEED` if
+//! it is set, and each nonce from the resource it seals. The same sources always build
+//! the same binary. The key is in the binary either way, so a secret seed only keeps it
+//! from being derived without the binary. It is written to `$OUT_DIR/resource_key.rs`
+//! next to the sealed resources.
+
+use std::{env, fs, path::PathBuf};
+
+use sha2::{Digest, Sha256};
+
+#[allow(dead_code)]
+#[path = "src/resources/cipher.rs"]
+mod cipher;
+
+const RESOURCE_DIR: &str = "src/resources";
+const RESOURCES: &[&str] = &["kiDebugger.tsp", "tspdbg.tsp"];
+const SEED_VAR: &str = "KIC_RESOURCE_SEED";
+
+/// Fill `out` with the SHA-256 of `parts`, continued with a counter for longer outputs.
+fn derive(parts: &[&[u8]], out: &mut [u8]) {
+    for (counter, chunk) in (0u8..).zip(out.chunks_mut(32)) {
+        let mut hasher = Sha256::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher.update([counter]);
+        let digest = hasher.finalize();
+        chunk.copy_from_slice(&digest[..chunk.len()]);
+    }
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed={RESOURCE_DIR}/cipher.rs");
+    println!("cargo:rerun-if-env-changed={SEED_VAR}");
+
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").expect("cargo should set OUT_DIR"));
+    let seed = env::var(SEED_VAR).unwrap_or_else(|_| {
+        concat!(env!("CARGO_PKG_NAME"), "-", env!("CARGO_PKG_VERSION")).to_string()
+    });
+
+    let mut key = [0u8; cipher::KEY_LEN];
+    derive(&[b"key", seed.as_bytes()], &mut key);
+
+    for name in RESOURCES {
+        let path = PathBuf::from(RESOURCE_DIR).join(name);
+        println!("cargo:rerun-if-changed={}", path.display());
+
+        let plaintext = fs::read(&path)
+            .unwrap_or_else(|e| panic!("should be able to read {}: {e}", path.display()));
+
+        // A resource that changes gets a new nonce, so no nonce is used for two
+        // different plaintexts under the same key.
+        let mut nonce = [0u8; cipher::NONCE_LEN];
+        derive(&[b"nonce", seed.as_bytes(), &plaintext], &mut nonce);
+
+        fs::write(
+            out_dir.join(format!("{name}.enc")),
+            cipher::seal(&key, &nonce, &plaintext),
+        )
+        .expect("should be able to write encrypted resource");
+    }
+
+    fs::write(
+        out_dir.join("resource_key.rs"),
+        format!("const RESOURCE_KEY: [u8; {}] = {key:?};\n", cipher::KEY_LEN),
+    )
+    .expect("should be able to write resource key");
+}
@@ -1,4 +1,5 @@
 use crate::debugger::breakpoint::Breakpoint;
+use crate::debugger::instrument_log::LogAction;
 use crate::debugger::variable::VariableInfo;
 use crate::debugger::watchpoint::WatchpointInfo;
 
@@ -17,6 +18,13 @@ pub enum Request {
     StartDebugger {
         file_path: String,
         break_points: Vec<Breakpoint>,
+        /// The source to debug if it wasn't given as a file on disk. `file_path` is then
+        /// a virtual file name.
+        source: Option<String>,
+    },
+    /// Debug a script that is saved on the instrument
+    AttachScript {
+        name: String,
     },
     Watchpoint {
         watchpoint_info: WatchpointInfo,
@@ -25,11 +33,23 @@ pub enum Request {
         vairable_info: VariableInfo,
     },
     Run,
+    /// Pause the running debuggee at its next line
+    Pause,
     StepOver,
     StepIn,
     StepOut,
+    /// Show the previous recorded stop
+    StepBack,
+    /// Show the previous recorded stop at a breakpoint
+    ReverseContinue,
     ClearBreakPoints,
+    /// Control the log of the on-instrument debugger
+    Log {
+        action: LogAction,
+    },
     Exit,
+    /// The user pressed Ctrl-C
+    Interrupt,
     Help {
         sub_cmd: Option<String>,
     },
@@ -0,0 +1,83 @@
+//! Fetch the source of a script that is saved on the instrument so it can be debugged
+//! without a local file.
+use std::path::PathBuf;
+
+/// Marks the start of the script source printed by the instrument.
+pub const SOURCE_BEGIN: &str = "<kic-debugger-source>";
+/// Marks the end of the script source printed by the instrument.
+pub const SOURCE_END: &str = "</kic-debugger-source>";
+/// Printed instead of the source if no script with the requested name is saved.
+const NOT_FOUND: &str = "<kic-debugger-source-not-found/>";
+
+/// Whether `name` can be used as a script name in TSP, i.e. is a Lua identifier.
+#[must_use]
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The TSP command that makes the instrument print the source of the saved script
+/// `name`. `name` must be a valid name, see [`is_valid_name`].
+#[must_use]
+pub fn query(name: &str) -> String {
+    format!(
+        "do local found = false local cat = (script.user and script.user.catalog) or script.catalog for n in cat() do if n == \"{name}\" then found = true end end if found and {name} and {name}.source then print(\"{SOURCE_BEGIN}\") print({name}.source) print(\"{SOURCE_END}\") else print(\"{NOT_FOUND}{SOURCE_END}\") end end\n"
+    )
+}
+
+/// Find the script source in the given instrument output.
+///
+/// Returns `None` if the output does not contain the source, e.g. because the script
+/// was not found.
+#[must_use]
+pub fn parse(output: &str) -> Option<String> {
+    let end = output.rfind(SOURCE_END)?;
+    let begin = output[..end].rfind(SOURCE_BEGIN)?;
+    let source = &output[begin.checked_add(SOURCE_BEGIN.len())?..end];
+    // `print()` adds a line break after the begin marker and after the source.
+    let source = source
+        .strip_prefix("\r\n")
+        .or_else(|| source.strip_prefix('\n'))
+        .unwrap_or(source);
+    let source = source
+        .strip_suffix("\r\n")
+        .or_else(|| source.strip_suffix('\n'))
+        .unwrap_or(source);
+    Some(source.to_string())
+}
+
+/// Where the local copy of an attached script is kept, so that a front end can map
+/// the lines reported by the debugger to a file.
+#[must_use]
+pub fn local_copy_path(name: &str) -> PathBuf {
+    std::env::temp_dir()
+        .join("kic-debug")
+        .join(format!("{name}.tsp"))
+}
+
+#[cfg(test)]
+mod unit {
+    use super::{is_valid_name, parse};
+
+    #[test]
+    fn parse_source() {
+        let output = "<kic-debugger-source>\nprint(1)\n\nprint(2)\n</kic-debugger-source>\n";
+
+        assert_eq!(parse(output).as_deref(), Some("print(1)\n\nprint(2)"));
+        assert_eq!(
+            parse("<kic-debugger-source-not-found/></kic-debugger-source>\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn names_must_be_identifiers() {
+        assert!(is_valid_name("autoexec"));
+        assert!(is_valid_name("_sweep2"));
+        assert!(!is_valid_name("2sweep"));
+        assert!(!is_valid_name("x.source) print(1"));
+    }
+}
@@ -0,0 +1,179 @@
+//! Find and remove what a debug session may leave behind on the instrument: the
+//! debugger scripts, debuggee scripts and the globals defined by `kiDebugger`.
+//!
+//! Debuggee scripts are recognized by the names that
+//! [`script_name`](super::script_name::script_name) makes, so other scripts of the user
+//! that start with `kic_` are left alone.
+use super::{
+    cache::{CACHED_KIDEBUGGER, CACHED_TSPDBG},
+    handshake::KI_DEBUGGER_GLOBAL_FUNCTIONS,
+    mocks::MOCKS_SCRIPT_NAME,
+    script_name::{lua_pattern, MAX_SCRIPT_NAME_LEN},
+};
+
+/// Marks the start of the leftover query response printed by the instrument.
+pub const CLEANUP_BEGIN: &str = "<kic-debugger-leftovers>";
+/// Marks the end of the leftover query response printed by the instrument.
+pub const CLEANUP_END: &str = "</kic-debugger-leftovers>";
+
+/// Globals that `kiDebugger` defines in addition to [`KI_DEBUGGER_GLOBAL_FUNCTIONS`].
+const OTHER_DEBUGGER_GLOBALS: &[&str] = &[
+    "kiDebuggerVersion",
+    "kiSetTableToStringFunction",
+    "getscripts",
+];
+
+/// The scripts that only a debug session saves, besides the debuggee scripts.
+const DEBUGGER_SCRIPTS: &[&str] = &[
+    "kiDebugger",
+    "tspdbg",
+    CACHED_KIDEBUGGER,
+    CACHED_TSPDBG,
+    MOCKS_SCRIPT_NAME,
+];
+
+/// Whether `name` is a debugger global rather than a script.
+fn is_global(name: &str) -> bool {
+    KI_DEBUGGER_GLOBAL_FUNCTIONS.contains(&name) || OTHER_DEBUGGER_GLOBALS.contains(&name)
+}
+
+/// The debugger scripts and globals found on the instrument.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Leftovers {
+    /// The names of the leftover scripts and globals
+    pub names: Vec<String>,
+}
+
+impl Leftovers {
+    /// The TSP command that makes the instrument print the names of any leftover
+    /// debugger scripts (loaded or saved) and globals.
+    #[must_use]
+    pub fn query() -> String {
+        let quoted = |names: &[&str]| {
+            names
+                .iter()
+                .map(|n| format!("[\"{n}\"]=true"))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        let globals = KI_DEBUGGER_GLOBAL_FUNCTIONS
+            .iter()
+            .chain(OTHER_DEBUGGER_GLOBALS)
+            .map(|g| format!("\"{g}\""))
+            .collect::<Vec<_>>()
+            .join(",");
+        let scripts = quoted(DEBUGGER_SCRIPTS);
+        let pattern = lua_pattern();
+        format!(
+            "do local l = {{}} local s = {{{scripts}}} local function chk(n) if type(n) == \"string\" and (s[n] or (string.len(n) <= {MAX_SCRIPT_NAME_LEN} and string.find(n, \"{pattern}\"))) then l[n] = true end end for n in _G do chk(n) end local cat = (script.user and script.user.catalog) or script.catalog if cat then for n in cat() do chk(n) end end for _, n in ipairs({{{globals}}}) do if _G[n] ~= nil then l[n] = true end end local r = {{}} for n in l do table.insert(r, n) end print(\"{CLEANUP_BEGIN}\" .. table.concat(r, \",\") .. \"{CLEANUP_END}\") end\n"
+        )
+    }
+
+    /// Find and parse the leftover query response in the given instrument output.
+    ///
+    /// Returns `None` if the output does not contain a complete response.
+    #[must_use]
+    pub fn parse(output: &str) -> Option<Self> {
+        let end = output.rfind(CLEANUP_END)?;
+        let begin = output[..end].rfind(CLEANUP_BEGIN)?;
+        let response = &output[begin.checked_add(CLEANUP_BEGIN.len())?..end];
+
+        let mut names: Vec<String> = response
+            .split(',')
+            .map(str::trim)
+            .filter(|n| !n.is_empty())
+            .map(ToString::to_string)
+            .collect();
+        names.sort();
+
+        Some(Self { names })
+    }
+
+    /// Drop the given names, e.g. cached scripts that are meant to stay on the
+    /// instrument.
+    #[must_use]
+    pub fn without(mut self, keep: &[&str]) -> Self {
+        self.names.retain(|n| !keep.contains(&n.as_str()));
+        self
+    }
+
+    /// Whether nothing was left behind.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// The TSP commands that remove the leftovers.
+    #[must_use]
+    pub fn removal_commands(&self) -> Vec<String> {
+        self.names
+            .iter()
+            .map(|n| {
+                if is_global(n) {
+                    format!("{n} = nil\n")
+                } else {
+                    format!("{n} = nil script.delete(\"{n}\")\n")
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use std::{io::Write, time::Duration};
+
+    use kic_lib::{instrument::read_until, interface::NonBlock, model::simulator};
+
+    use super::{Leftovers, CLEANUP_END};
+    use crate::debugger::script_name::script_name;
+
+    #[test]
+    fn parse_and_remove_leftovers() {
+        let output = "<kic-debugger-leftovers>kic_test,kiRun,kiDebugger,kic_kiDebugger</kic-debugger-leftovers>\n";
+        let leftovers = Leftovers::parse(output)
+            .unwrap()
+            .without(&["kic_kiDebugger"]);
+
+        assert_eq!(leftovers.names, ["kiDebugger", "kiRun", "kic_test"]);
+        assert_eq!(
+            leftovers.removal_commands(),
+            [
+                "kiDebugger = nil script.delete(\"kiDebugger\")\n",
+                "kiRun = nil script.delete(\"kiRun\")\n",
+                "kic_test = nil script.delete(\"kic_test\")\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn only_debugger_scripts_are_found() {
+        let mut instrument = simulator::Instrument::new().unwrap();
+        instrument.set_nonblocking(true).unwrap();
+        let debuggee = script_name("measure", "/scripts/measure.tsp");
+        for name in ["kic_measure", "kic_tspdbg", debuggee.as_str()] {
+            instrument
+                .write_all(format!("script.new(\"x = 1\", \"{name}\").save()\n").as_bytes())
+                .unwrap();
+        }
+        instrument.write_all(Leftovers::query().as_bytes()).unwrap();
+
+        let output = read_until(
+            &mut instrument,
+            &[CLEANUP_END.to_string()],
+            100,
+            Duration::from_millis(10),
+        )
+        .unwrap();
+        let leftovers = Leftovers::parse(&output).unwrap();
+
+        assert_eq!(leftovers.names, [debuggee, "kic_tspdbg".to_string()]);
+    }
+
+    #[test]
+    fn nothing_left() {
+        let output = "<kic-debugger-leftovers></kic-debugger-leftovers>";
+
+        assert!(Leftovers::parse(output).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,98 @@
+//! Debugger events that this application acts on, parsed from the frames of the
+//! on-instrument debugger. Other frames are only passed on to the front end.
+use super::output::{parse_attributes, unescape, FRAME_START};
+
+/// An event reported by the on-instrument debugger.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DebugEvent {
+    /// An error was taken from the instrument error queue while breaking on instrument
+    /// errors. The debuggee is paused at `line` once all queued errors are reported.
+    InstrumentError {
+        line: u32,
+        code: i64,
+        message: String,
+        severity: i64,
+        node: i64,
+    },
+    /// The debuggee raised an error that ended it.
+    ExecutionFailed {
+        /// The error message with its traceback
+        error: String,
+        /// The name of the debugged chunk
+        name: Option<String>,
+    },
+}
+
+impl DebugEvent {
+    /// Parse the event in a debugger frame.
+    ///
+    /// Returns `None` if the frame doesn't hold an event this application acts on.
+    #[must_use]
+    pub fn parse(frame: &str) -> Option<Self> {
+        let element = frame.strip_prefix(FRAME_START)?.trim_start();
+        if let Some(attributes) = element.strip_prefix("<execution-failed ") {
+            return Some(Self::ExecutionFailed {
+                error: attribute(attributes, "error").unwrap_or_default(),
+                name: attribute(attributes, "name"),
+            });
+        }
+        let attributes = element.strip_prefix("<instrument-error ")?;
+        let number = |name: &str| attribute(attributes, name).and_then(|v| v.trim().parse().ok());
+        Some(Self::InstrumentError {
+            line: number("line").and_then(|l: i64| u32::try_from(l).ok())?,
+            code: number("code")?,
+            message: attribute(attributes, "message").unwrap_or_default(),
+            severity: number("severity").unwrap_or(0),
+            node: number("node").unwrap_or(0),
+        })
+    }
+}
+
+/// The unescaped value of the attribute `name` in the attributes of an element.
+fn attribute(attributes: &str, name: &str) -> Option<String> {
+    parse_attributes(attributes)
+        .into_iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, value)| unescape(&value))
+}
+
+#[cfg(test)]
+mod unit {
+    use super::{DebugEvent, FRAME_START};
+    use crate::debugger::output::FRAME_END;
+
+    #[test]
+    fn instrument_errors_are_parsed() {
+        let frame = format!("{FRAME_START}<instrument-error line='12' code='-224' message='Parameter &apos;x&apos; out of range&#x240a;' severity='2' node='1' />{FRAME_END}");
+
+        assert_eq!(
+            DebugEvent::parse(&frame),
+            Some(DebugEvent::InstrumentError {
+                line: 12,
+                code: -224,
+                message: "Parameter 'x' out of range\n".to_string(),
+                severity: 2,
+                node: 1,
+            })
+        );
+        assert_eq!(
+            DebugEvent::parse(&format!("{FRAME_START}<breakpoint line='3' />{FRAME_END}")),
+            None
+        );
+    }
+
+    #[test]
+    fn execution_failures_are_parsed() {
+        let frame = format!(
+            "{FRAME_START}<execution-failed error='t.tsp:3: boom' name='debug_t' />{FRAME_END}"
+        );
+
+        assert_eq!(
+            DebugEvent::parse(&frame),
+            Some(DebugEvent::ExecutionFailed {
+                error: "t.tsp:3: boom".to_string(),
+                name: Some("debug_t".to_string()),
+            })
+        );
+    }
+}
@@ -0,0 +1,220 @@
+//! A bounded history of the stops of the debuggee, so that earlier stops can be
+//! revisited without running the script again.
+//!
+//! Every `<stacks>` frame the on-instrument debugger sends at a stop is recorded. While
+//! an earlier stop is shown, stepping and continuing move forward through the history
+//! until the stop the instrument is paused at is reached again.
+use std::collections::VecDeque;
+
+use super::output::{FRAME_END, FRAME_START};
+
+/// How many stops are kept before the oldest is dropped.
+pub const HISTORY_LEN: usize = 200;
+
+/// A recorded stop of the debuggee.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stop {
+    /// The `<stacks>` frame sent at the stop
+    pub frame: String,
+    /// The current line of the top stack level
+    pub line: Option<u32>,
+}
+
+impl Stop {
+    fn new(frame: String) -> Self {
+        let line = frame
+            .split_once("<stack level='0' ")
+            .and_then(|(_, rest)| rest.split_once("currentline='"))
+            .and_then(|(_, rest)| rest.split_once('\''))
+            .and_then(|(line, _)| line.parse().ok());
+        Self { frame, line }
+    }
+}
+
+/// The recorded stops, oldest first.
+#[derive(Debug, Clone)]
+pub struct History {
+    stops: VecDeque<Stop>,
+    capacity: usize,
+    /// The index of the stop that is shown, `None` for the stop the debuggee is at
+    position: Option<usize>,
+    /// Whether the debuggee resumed since the last recorded stop
+    resumed: bool,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(HISTORY_LEN)
+    }
+}
+
+impl History {
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            stops: VecDeque::new(),
+            capacity: capacity.max(1),
+            position: None,
+            resumed: true,
+        }
+    }
+
+    /// Forget all stops, e.g. when a new session starts.
+    pub fn clear(&mut self) {
+        self.stops.clear();
+        self.position = None;
+        self.resumed = true;
+    }
+
+    /// Record the stops in the given debugger frames. A stop that is reported again
+    /// without the debuggee resuming (e.g. after a variable was set) replaces the
+    /// recorded one.
+    pub fn observe(&mut self, frames: &[String]) {
+        for frame in frames {
+            if frame.contains("<resume-") {
+                self.resumed = true;
+            } else if frame.contains("<stacks>") {
+                if !self.resumed {
+                    self.stops.pop_back();
+                }
+                if self.stops.len() == self.capacity {
+                    self.stops.pop_front();
+                }
+                self.stops.push_back(Stop::new(frame.clone()));
+                self.position = None;
+                self.resumed = false;
+            }
+        }
+    }
+
+    /// Whether an earlier stop is shown instead of the one the debuggee is at.
+    #[must_use]
+    pub fn is_replaying(&self) -> bool {
+        self.position.is_some()
+    }
+
+    /// The index of the shown stop and the number of recorded stops.
+    #[must_use]
+    pub fn position(&self) -> (usize, usize) {
+        let len = self.stops.len();
+        (self.position.unwrap_or(len.saturating_sub(1)), len)
+    }
+
+    /// The stop the debuggee is at, or was at when it last stopped.
+    #[must_use]
+    pub fn latest(&self) -> Option<&Stop> {
+        self.stops.back()
+    }
+
+    /// Render the shown position as a debugger frame for front ends.
+    #[must_use]
+    pub fn render_frame(&self) -> String {
+        let (index, len) = self.position();
+        format!(
+            "{FRAME_START}<history position='{}' count='{len}' replaying='{}' />{FRAME_END}\n",
+            index + 1,
+            self.is_replaying()
+        )
+    }
+
+    /// Show the stop before the shown one.
+    ///
+    /// Returns `None` if the oldest recorded stop is shown.
+    pub fn step_back(&mut self) -> Option<&Stop> {
+        self.back(|_| true)
+    }
+
+    /// Show the latest earlier stop whose line satisfies `is_breakpoint`, or the oldest
+    /// recorded stop if there is none.
+    ///
+    /// Returns `None` if the oldest recorded stop is shown.
+    pub fn reverse_continue(&mut self, is_breakpoint: impl Fn(u32) -> bool) -> Option<&Stop> {
+        self.back(|stop| stop.line.is_some_and(&is_breakpoint))
+    }
+
+    /// Show the stop after the shown one.
+    ///
+    /// Returns `None` if the stop the debuggee is at is shown.
+    pub fn step_forward(&mut self) -> Option<&Stop> {
+        self.forward(|_| true)
+    }
+
+    /// Show the next stop whose line satisfies `is_breakpoint`, or the stop the debuggee
+    /// is at if there is none.
+    ///
+    /// Returns `None` if the stop the debuggee is at is shown.
+    pub fn continue_forward(&mut self, is_breakpoint: impl Fn(u32) -> bool) -> Option<&Stop> {
+        self.forward(|stop| stop.line.is_some_and(&is_breakpoint))
+    }
+
+    fn back(&mut self, is_target: impl Fn(&Stop) -> bool) -> Option<&Stop> {
+        let (current, _) = self.position();
+        if current == 0 {
+            return None;
+        }
+        let target = (0..current)
+            .rev()
+            .find(|&i| is_target(&self.stops[i]))
+            .unwrap_or(0);
+        self.position = Some(target);
+        self.stops.get(target)
+    }
+
+    fn forward(&mut self, is_target: impl Fn(&Stop) -> bool) -> Option<&Stop> {
+        let current = self.position?;
+        let last = self.stops.len().checked_sub(1)?;
+        let target = (current + 1..last)
+            .find(|&i| is_target(&self.stops[i]))
+            .unwrap_or(last);
+        self.position = (target < last).then_some(target);
+        self.stops.get(target)
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::History;
+
+    fn stop(line: u32) -> String {
+        format!("<stacks>\n    <stack level='0' currentline='{line}' func='f'>")
+    }
+
+    fn resume() -> String {
+        "<resume-stepover />".to_string()
+    }
+
+    #[test]
+    fn navigate_recorded_stops() {
+        let mut history = History::new(3);
+        for line in 1..=4 {
+            history.observe(&[stop(line), resume()]);
+        }
+        // A stop reported again replaces the recorded one.
+        history.observe(&[stop(5), stop(5)]);
+
+        assert_eq!(history.position(), (2, 3));
+        assert_eq!(history.step_back().unwrap().line, Some(4));
+        assert_eq!(history.step_back().unwrap().line, Some(3));
+        assert!(history.step_back().is_none());
+        assert_eq!(history.step_forward().unwrap().line, Some(4));
+        assert_eq!(history.step_forward().unwrap().line, Some(5));
+        assert!(!history.is_replaying());
+        assert!(history.step_forward().is_none());
+    }
+
+    #[test]
+    fn reverse_continue_stops_at_breakpoints() {
+        let mut history = History::default();
+        for line in [2, 7, 3, 7, 4] {
+            history.observe(&[stop(line), resume()]);
+        }
+
+        assert_eq!(history.reverse_continue(|l| l == 7).unwrap().line, Some(7));
+        assert_eq!(history.position(), (3, 5));
+        assert_eq!(history.reverse_continue(|l| l == 7).unwrap().line, Some(7));
+        assert_eq!(history.reverse_continue(|l| l == 7).unwrap().line, Some(2));
+        assert_eq!(history.continue_forward(|l| l == 7).unwrap().line, Some(7));
+        assert_eq!(history.continue_forward(|l| l == 9).unwrap().line, Some(4));
+        assert!(!history.is_replaying());
+    }
+}
@@ -0,0 +1,76 @@
+//! Mocks that replace instrument commands while the debuggee runs, loaded from a local
+//! Lua file.
+//!
+//! The file is uploaded as a script and run right before the debuggee. In it, `mock`
+//! replaces a global or a field and remembers the original, e.g.
+//!
+//! ```lua
+//! mock("smua.measure.i", function() return 1.05e-3 end)
+//! mock("delay", function() end)
+//! ```
+//!
+//! The originals are restored when the session ends or is restarted. Plain assignments
+//! in the file work too, but are not undone.
+
+/// The name of the mocks script on the instrument.
+pub const MOCKS_SCRIPT_NAME: &str = "kic_mocks";
+
+/// The TSP command that restores everything replaced with `mock`. It does nothing if
+/// the debugger isn't loaded.
+pub const RESTORE_MOCKS: &str = "if kiRestoreMocks then kiRestoreMocks() end\n";
+
+/// The script that runs the mocks in `source` with `mock` defined.
+///
+/// `mock` is declared on the first line of the source, so the line numbers in error
+/// messages match the file. The `;` keeps a source that starts with `(` from being
+/// read as a call of `kiMock`.
+#[must_use]
+pub fn script(source: &str) -> String {
+    format!("local mock = kiMock; {source}")
+}
+
+/// The TSP command that runs the uploaded mocks.
+#[must_use]
+pub fn run() -> String {
+    format!("{MOCKS_SCRIPT_NAME}()\n")
+}
+
+#[cfg(test)]
+mod unit {
+    use std::{io::Write, time::Duration};
+
+    use kic_lib::{instrument::read_until, interface::NonBlock, model::simulator};
+
+    use super::script;
+
+    #[test]
+    fn line_numbers_are_kept() {
+        let source = "mock(\"delay\", function() end)\nmock(\"smua.measure.i\", f)\n";
+        let script = script(source);
+
+        assert_eq!(script.lines().count(), source.lines().count());
+        assert!(script.starts_with("local mock = kiMock; mock(\"delay\""));
+    }
+
+    #[test]
+    fn a_leading_parenthesis_is_not_a_call() {
+        let mut instrument = simulator::Instrument::new().unwrap();
+        instrument.set_nonblocking(true).unwrap();
+
+        instrument
+            .write_all(b"kiMock = function(name) print(\"mocked \" .. name) end\n")
+            .unwrap();
+        instrument
+            .write_all(format!("{}\n", script("(mock)(\"delay\")")).as_bytes())
+            .unwrap();
+
+        let output = read_until(
+            &mut instrument,
+            &["mocked".to_string()],
+            100,
+            Duration::from_millis(10),
+        )
+        .unwrap();
+        assert_eq!(output, "mocked delay");
+    }
+}
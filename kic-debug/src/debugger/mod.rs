@@ -1,39 +1,124 @@
 use clap::{arg, value_parser, Command};
 use colored::Colorize;
-use kic_lib::instrument::{clear_output_queue, Instrument};
+use kic_lib::instrument::{clear_output_queue, info::InstrumentInfo, read_until, Instrument};
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashMap,
     fmt::Display,
     fs,
     io::{Error, Write},
     path::{Path, PathBuf},
-    sync::mpsc::{channel, SendError, Sender, TryRecvError},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, SendError, Sender, TryRecvError},
+        Mutex, PoisonError,
+    },
     thread::{self, JoinHandle},
-    time::Duration,
+    time::{Duration, Instant},
 };
+pub mod attach;
 pub mod breakpoint;
+pub mod cache;
+pub mod changes;
+pub mod cleanup;
+pub mod crash;
+pub mod events;
+pub mod frontend;
+pub mod handshake;
+pub mod history;
+pub mod instrument_log;
+pub mod mocks;
+pub mod output;
+pub mod probe;
+pub mod rack;
+pub mod reconnect;
+pub mod script_name;
+pub mod source;
 pub mod variable;
 pub mod watchpoint;
-use self::{breakpoint::Breakpoint, variable::VariableInfo, watchpoint::WatchpointInfo};
+use self::{
+    attach::SOURCE_END,
+    breakpoint::Breakpoint,
+    cache::{CachedHashes, CACHED_KIDEBUGGER, CACHED_TSPDBG, CACHE_END},
+    changes::Snapshot,
+    cleanup::{Leftovers, CLEANUP_END},
+    crash::{CrashReport, ScriptReport},
+    events::DebugEvent,
+    frontend::{CommandSource, StdinSource},
+    handshake::{DebuggerVersion, HANDSHAKE_END, KI_DEBUGGER_GLOBAL_FUNCTIONS},
+    history::{History, Stop},
+    instrument_log::{LogAction, LogStatus, LOG_END},
+    output::{Demultiplexer, OutputKind},
+    reconnect::{DebuggeeStatus, ReconnectPolicy, STATUS_END, STATUS_QUERY},
+    variable::{Scope, VariableInfo},
+    watchpoint::WatchpointInfo,
+};
 pub use crate::resources::{KIDEBUGGER_TSP, TSPDBG_TSP};
 use crate::{
     command::Request,
     error::{DebugError, Result},
+    minify::minify,
 };
 use regex::Regex;
 
+/// How many times [`Debugger::cleanup`] tries to remove leftovers before giving up.
+pub const CLEANUP_ATTEMPTS: usize = 3;
+
+/// A second Ctrl-C within this window exits immediately without cleaning up.
+pub const FORCE_EXIT_WINDOW: Duration = Duration::from_secs(2);
+
+/// How long incomplete instrument output is held back before it is shown anyway.
+const OUTPUT_FLUSH_DELAY: Duration = Duration::from_millis(100);
+
+/// How the session goes on after a request of the user.
+enum Flow {
+    Continue,
+    Exit,
+    /// The user interrupted the session with Ctrl-C
+    Interrupt,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DebugInfo {
-    #[serde(rename = "FileName")]
+    #[serde(rename = "FileName", default)]
     pub file_name: String,
     #[serde(rename = "BreakPoints")]
     pub break_points: Vec<Breakpoint>,
+    /// The source to debug instead of the contents of `file_name`
+    #[serde(rename = "Source", default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
 }
 pub struct Debugger {
     instrument: Box<dyn Instrument>, // reference of the instrument
     debuggee_file_name: Option<String>,
+    script_names: HashMap<String, String>,
+    attached_script: Option<String>,
     debuggee_file_path: Option<PathBuf>,
+    debuggee_source: Option<String>,
     breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<WatchpointInfo>,
+    /// The source of the script that is debugged, as uploaded
+    script_source: Option<String>,
+    instrument_info: Option<InstrumentInfo>,
+    crash_dir: PathBuf,
+    /// Instrument output that was read while waiting for something else and still has
+    /// to go through the read loop
+    unread_output: String,
+    /// Whether the instrument was cleaned up, so it isn't cleaned up again when the
+    /// debugger is dropped
+    cleaned_up: bool,
+    cache_scripts: bool,
+    minify_scripts: bool,
+    highlight_changes: bool,
+    stop_on_exit: bool,
+    break_on_instrument_error: bool,
+    pausable: bool,
+    mocks_file: Option<PathBuf>,
+    previous_stop: Option<Snapshot>,
+    history: History,
+    reconnect: Option<ReconnectPolicy>,
+    output: Demultiplexer,
+    commands: Option<Box<dyn CommandSource>>,
 }
 
 impl Debugger {
@@ -44,11 +129,222 @@ impl Debugger {
         Self {
             instrument: inst,
             debuggee_file_name: None,
+            script_names: HashMap::new(),
+            attached_script: None,
             debuggee_file_path: None,
+            debuggee_source: None,
             breakpoints: Default::default(),
+            watchpoints: Vec::new(),
+            script_source: None,
+            instrument_info: None,
+            crash_dir: crash::default_dir(),
+            unread_output: String::new(),
+            cleaned_up: false,
+            cache_scripts: false,
+            minify_scripts: false,
+            highlight_changes: false,
+            stop_on_exit: false,
+            break_on_instrument_error: false,
+            pausable: false,
+            mocks_file: None,
+            previous_stop: None,
+            history: History::default(),
+            reconnect: None,
+            output: Demultiplexer::default(),
+            commands: None,
+        }
+    }
+
+    /// Save the debugger scripts to the non-volatile memory of the instrument and reuse
+    /// them in later sessions as long as their content hash matches.
+    ///
+    /// Cached scripts keep their source on the instrument so that the hash can be
+    /// checked.
+    pub fn set_cache_scripts(&mut self, enable: bool) {
+        self.cache_scripts = enable;
+    }
+
+    /// Minify the debuggee script before uploading it. Line numbers are preserved, so
+    /// breakpoints and error messages are unaffected.
+    pub fn set_minify_scripts(&mut self, enable: bool) {
+        self.minify_scripts = enable;
+    }
+
+    /// Report the variables and watch values that changed since the previous stop, as a
+    /// `<changes>` debugger frame for front ends and as a summary line.
+    pub fn set_highlight_changes(&mut self, enable: bool) {
+        self.highlight_changes = enable;
+    }
+
+    /// Keep the session paused after the last line of the main chunk, so the final
+    /// globals can be inspected before the debuggee ends.
+    pub fn set_stop_on_exit(&mut self, enable: bool) {
+        self.stop_on_exit = enable;
+    }
+
+    /// Pause the debuggee whenever the instrument error queue is not empty and report
+    /// the queued errors. The errors are removed from the queue when they are reported.
+    pub fn set_break_on_instrument_error(&mut self, enable: bool) {
+        self.break_on_instrument_error = enable;
+    }
+
+    /// Let `.debug pause` pause the running debuggee. The instrument then checks for a
+    /// pause request while the debuggee runs, which slows down every line a little.
+    pub fn set_pausable(&mut self, enable: bool) {
+        self.pausable = enable;
+    }
+
+    /// Run the mocks in the Lua file at `path` before the debuggee, see [`mocks`]. The
+    /// file is read again whenever a session starts.
+    pub fn set_mocks_file(&mut self, path: PathBuf) {
+        self.mocks_file = Some(path);
+    }
+
+    /// Write crash reports to `dir` instead of [`crash::default_dir`].
+    pub fn set_crash_dir(&mut self, dir: PathBuf) {
+        self.crash_dir = dir;
+    }
+
+    /// Reconnect to the instrument with the given policy if the connection is lost
+    /// during the session.
+    pub fn set_reconnect_policy(&mut self, policy: ReconnectPolicy) {
+        self.reconnect = Some(policy);
+    }
+
+    /// Also write the instrument output of the given kind to the file at `path`.
+    ///
+    /// # Errors
+    /// Returns an IO error if the file can't be created.
+    pub fn tee_output(&mut self, kind: OutputKind, path: &Path) -> Result<()> {
+        self.output.tee(kind, path)
+    }
+
+    /// Write the instrument output of the given kind to `sink` instead of the front
+    /// end.
+    pub fn set_output_sink(&mut self, kind: OutputKind, sink: Box<dyn std::io::Write + Send>) {
+        self.output.set_sink(kind, sink);
+    }
+
+    /// Read user commands from `commands` and write all output to `output` instead of
+    /// stdin and stdout.
+    pub fn set_front_end(
+        &mut self,
+        commands: Box<dyn CommandSource>,
+        output: Box<dyn std::io::Write + Send>,
+    ) {
+        self.commands = Some(commands);
+        self.output.set_console(output);
+    }
+
+    /// Tell the user about the events in the given debugger frames that need more than
+    /// the front end shows.
+    fn report_events(&mut self, frames: &[String]) -> Result<()> {
+        for event in frames.iter().filter_map(|f| DebugEvent::parse(f)) {
+            match event {
+                DebugEvent::InstrumentError {
+                    line,
+                    code,
+                    message,
+                    severity,
+                    node,
+                } => self.println_flush(
+                    &format!(
+                        "Instrument error before line {line}: {code}, {message} (severity {severity}, node {node})"
+                    )
+                    .red(),
+                ),
+                DebugEvent::ExecutionFailed { error, .. } => {
+                    let error_queue = self.drain_error_queue();
+                    self.write_crash_report(&format!("execution failed: {error}"), error_queue);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Read and clear the instrument error queue without showing it. The output of the
+    /// session that arrives in the meantime is left for the read loop.
+    ///
+    /// Returns an empty queue if it could not be read.
+    fn drain_error_queue(&mut self) -> Vec<crash::QueuedError> {
+        let output = self
+            .instrument
+            .write_all(crash::error_queue_query().as_bytes())
+            .map_err(DebugError::from)
+            .and_then(|()| {
+                Ok(read_until(
+                    &mut *self.instrument,
+                    &[crash::ERRORS_END.to_string()],
+                    500,
+                    Duration::from_millis(10),
+                )?)
+            });
+        let output = match output {
+            Ok(output) => output,
+            Err(e) => {
+                tracing::warn!("Unable to read the instrument error queue: {e}");
+                return Vec::new();
+            }
+        };
+        let (before, rest) = output
+            .split_once(crash::ERRORS_BEGIN)
+            .unwrap_or((&output, ""));
+        let after = rest
+            .split_once(crash::ERRORS_END)
+            .map_or("", |(_, after)| after);
+        self.unread_output.push_str(before);
+        self.unread_output.push_str(after);
+        crash::parse_error_queue(&output)
+    }
+
+    /// Write a crash report for a session that failed for `reason` and tell the user
+    /// where to find it.
+    fn write_crash_report(&mut self, reason: &str, error_queue: Vec<crash::QueuedError>) {
+        let mut report = CrashReport::new(reason);
+        report.instrument.clone_from(&self.instrument_info);
+        report.script = self.script_source.clone().map(|source| {
+            ScriptReport::new(
+                self.attached_script
+                    .clone()
+                    .or_else(|| self.debuggee_file_name.clone()),
+                self.debuggee_file_path.clone(),
+                source,
+            )
+        });
+        report.breakpoints.clone_from(&self.breakpoints);
+        report.watchpoints.clone_from(&self.watchpoints);
+        report.last_stop = self.history.latest().map(|stop| stop.frame.clone());
+        report.error_queue = error_queue;
+        report.output_tail = self.output.tail().to_string();
+        match report.write(&self.crash_dir) {
+            Ok(path) => {
+                self.println_flush(&format!("Crash report written to {}", path.display()).yellow());
+            }
+            Err(e) => self.println_flush(&format!("Unable to write crash report: {e}").red()),
         }
     }
 
+    /// Compare the stops in the given debugger frames with the previous stop and report
+    /// what changed.
+    fn track_changes(&mut self, frames: Vec<String>) -> Result<()> {
+        if !self.highlight_changes {
+            return Ok(());
+        }
+        for snapshot in frames.iter().filter_map(|f| Snapshot::parse(f)) {
+            if let Some(previous) = &self.previous_stop {
+                let changes = snapshot.changes_since(previous);
+                if !changes.is_empty() {
+                    self.output
+                        .write_console(&changes::render_frame(&changes))?;
+                    let summary = changes::render_summary(&changes);
+                    self.println_flush(&summary.yellow());
+                }
+            }
+            self.previous_stop = Some(snapshot);
+        }
+        Ok(())
+    }
+
     // Funtion to handle all the special characters in the tsp script
     // * `script_name` - A String holds file name
     fn format_scriptname(mut script_name: String) -> String {
@@ -63,26 +359,16 @@ impl Debugger {
         script_name
     }
 
-    fn print_flush<D: Display>(string: &D) -> Result<()> {
-        print!("{string}");
-        let pr = std::io::stdout().flush();
-        match pr {
-            Ok(_) => {}
-            Err(e) => {
-                eprintln!("Error: {e:?}");
-            }
+    fn print_flush<D: Display>(&mut self, string: &D) -> Result<()> {
+        if let Err(e) = self.output.write_console(&string.to_string()) {
+            eprintln!("Error: {e:?}");
         }
         Ok(())
     }
 
-    fn println_flush<D: Display>(string: &D) {
-        println!("{string}");
-        let pr = std::io::stdout().flush();
-        match pr {
-            Ok(_) => {}
-            Err(e) => {
-                eprintln!("Error {e:?}");
-            }
+    fn println_flush<D: Display>(&mut self, string: &D) {
+        if let Err(e) = self.output.write_console(&format!("{string}\n")) {
+            eprintln!("Error {e:?}");
         }
     }
 
@@ -98,14 +384,8 @@ impl Debugger {
         file_content: &str,
         breakpoints: Vec<Breakpoint>,
     ) -> Result<()> {
-        self.load_debugger_files()?;
-        self.clear_debugger_file_sources()?;
-
-        self.clear_breakpoints()?;
-
-        for item in breakpoints {
-            self.set_breakpoint(&item)?;
-        }
+        self.prepare_debugger(breakpoints)?;
+        self.attached_script = None;
 
         // to remove extension from file name
         let file_path = Path::new(&file_name);
@@ -113,11 +393,24 @@ impl Debugger {
         let Some(file_name_str) = file else {
             return Err(DebugError::Other("Invalid File Name".to_string()));
         };
-        let file_name = Self::format_scriptname(file_name_str.to_string_lossy().to_string());
-        let mut script_name = format!("kic_{file_name}");
+        let stem = Self::format_scriptname(file_name_str.to_string_lossy().to_string());
+        // The same file keeps its on-instrument name for the whole session.
+        let key = self
+            .debuggee_file_path
+            .as_ref()
+            .map_or_else(|| file_name.to_string(), |p| p.display().to_string());
+        let script_name = self
+            .script_names
+            .entry(key)
+            .or_insert_with_key(|key| script_name::script_name(&stem, key))
+            .clone();
         self.debuggee_file_name = Some(script_name.clone());
-        script_name.truncate(31);
-        // script_name.truncate(255);
+        let file_content = if self.minify_scripts {
+            minify(file_content)
+        } else {
+            file_content.to_string()
+        };
+        self.script_source = Some(file_content.clone());
         self.instrument.write_script(
             script_name.clone().as_bytes(),
             file_content.as_bytes(),
@@ -134,23 +427,258 @@ impl Debugger {
         Ok(())
     }
 
+    /// Debug a script that is saved on the instrument. Its source is fetched and kept in
+    /// a local copy so that the reported lines can be mapped to a file.
+    /// * `name` - The name of the saved script
+    ///
+    /// # Errors
+    /// Returns a [`DebugError::CommandError`] if `name` isn't a valid script name or no
+    /// script with that name is saved on the instrument. IO Errors from the instrument
+    /// or from writing the local copy may also occur.
+    pub fn attach_script(&mut self, name: &str) -> Result<()> {
+        if !attach::is_valid_name(name) {
+            return Err(DebugError::CommandError {
+                details: format!("'{name}' is not a valid script name"),
+            });
+        }
+
+        self.instrument.write_all(attach::query(name).as_bytes())?;
+        let output = read_until(
+            &mut *self.instrument,
+            &[SOURCE_END.to_string()],
+            1000,
+            Duration::from_millis(10),
+        )?;
+        let Some(source) = attach::parse(&output) else {
+            return Err(DebugError::CommandError {
+                details: format!("no script named '{name}' is saved on the instrument"),
+            });
+        };
+
+        let local_copy = attach::local_copy_path(name);
+        if let Some(dir) = local_copy.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&local_copy, &source)?;
+        self.println_flush(&format!(
+            "Debugging '{name}' from the instrument, local copy: {}",
+            local_copy.display()
+        ));
+
+        let breakpoints = self.breakpoints.clone();
+        self.prepare_debugger(breakpoints)?;
+        self.debuggee_file_path = Some(local_copy);
+        // The script belongs to the user, so it must not be removed on exit.
+        self.debuggee_file_name = None;
+        self.attached_script = Some(name.to_string());
+        self.script_source = Some(source);
+
+        self.instrument.write_all(
+            format!("kiExecuteWithDebugger({name}.source,\"debug_{name}\",\"xml\")\n").as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    /// Load and verify the debugger and set the given breakpoints.
+    fn prepare_debugger(&mut self, breakpoints: Vec<Breakpoint>) -> Result<()> {
+        self.verify_instrument_support()?;
+        // The session leaves the debugger on the instrument again.
+        self.cleaned_up = false;
+        self.previous_stop = None;
+        self.history.clear();
+        // The originals are lost once the debugger is reloaded.
+        self.instrument.write_all(mocks::RESTORE_MOCKS.as_bytes())?;
+        self.load_debugger_files()?;
+        self.verify_debugger_version()?;
+        self.clear_debugger_file_sources()?;
+
+        self.clear_breakpoints()?;
+
+        for item in breakpoints {
+            self.set_breakpoint(&item)?;
+        }
+        self.instrument
+            .write_all(format!("kiSetStopOnExit({})\n", self.stop_on_exit).as_bytes())?;
+        self.instrument.write_all(
+            format!("kiSetBreakOnError({})\n", self.break_on_instrument_error).as_bytes(),
+        )?;
+        self.instrument
+            .write_all(format!("kiSetPausable({})\n", self.pausable).as_bytes())?;
+
+        if let Some(path) = &self.mocks_file {
+            let source = fs::read_to_string(path)?;
+            self.instrument.write_script(
+                mocks::MOCKS_SCRIPT_NAME.as_bytes(),
+                mocks::script(&source).as_bytes(),
+                false,
+                false,
+            )?;
+            self.instrument.write_all(mocks::run().as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Probe the instrument for the primitives the debugger needs. The firmware
+    /// version itself isn't checked, only what it provides.
+    ///
+    /// # Errors
+    /// Returns [`DebugError::UnsupportedFirmware`] if the instrument can't be debugged.
+    fn verify_instrument_support(&mut self) -> Result<()> {
+        let missing = self
+            .query_instrument(&probe::query(), probe::PROBE_END)?
+            .and_then(|output| probe::parse(&output));
+        probe::verify(self.instrument_info.as_ref(), missing.as_deref())
+    }
+
+    /// Switch the log of the on-instrument debugger on or off, or fetch it into a local
+    /// file and clear it on the instrument.
+    ///
+    /// # Errors
+    /// Returns a [`DebugError::CommandError`] if the instrument doesn't respond. IO
+    /// Errors from the instrument or from writing the local copy may also occur.
+    pub fn manage_log(&mut self, action: LogAction) -> Result<()> {
+        self.instrument.write_all(action.query().as_bytes())?;
+        let output = match read_until(
+            &mut *self.instrument,
+            &[LOG_END.to_string()],
+            3000,
+            Duration::from_millis(10),
+        ) {
+            Ok(output) => output,
+            Err(kic_lib::InstrumentError::Other(_)) => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let message = match LogStatus::parse(&output) {
+            Some(LogStatus::On) => {
+                "The debugger log is on and is written from the next session on".to_string()
+            }
+            Some(LogStatus::Off) => "The debugger log is off".to_string(),
+            Some(LogStatus::NoUsb) => {
+                "No USB drive is inserted in the instrument, the debugger log needs one".to_string()
+            }
+            Some(LogStatus::Fetched(log)) => {
+                let local_copy = instrument_log::local_copy_path();
+                if let Some(dir) = local_copy.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                fs::write(&local_copy, &log)?;
+                format!(
+                    "Fetched {} lines of the debugger log to {}",
+                    log.lines().count(),
+                    local_copy.display()
+                )
+            }
+            None => {
+                return Err(DebugError::CommandError {
+                    details: "the instrument did not respond to the log command".to_string(),
+                })
+            }
+        };
+        self.println_flush(&message.yellow());
+        Ok(())
+    }
+
     /// Load debugger files to the instrument.
     fn load_debugger_files(&mut self) -> Result<()> {
-        let tspdbg = TSPDBG_TSP.decrypt()?;
+        if self.cache_scripts {
+            return self.load_cached_debugger_files();
+        }
+
+        let tspdbg = minify(&TSPDBG_TSP.decrypt()?.to_string());
         self.instrument
-            .write_script(b"tspdbg", tspdbg.to_string().as_bytes(), false, true)?;
+            .write_script(b"tspdbg", tspdbg.as_bytes(), false, true)?;
 
-        let ki_debugger = KIDEBUGGER_TSP.decrypt()?;
-        self.instrument.write_script(
-            b"kiDebugger",
-            ki_debugger.to_string().as_bytes(),
-            false,
-            true,
-        )?;
+        let ki_debugger = minify(&KIDEBUGGER_TSP.decrypt()?.to_string());
+        self.instrument
+            .write_script(b"kiDebugger", ki_debugger.as_bytes(), false, true)?;
         Ok(())
     }
 
+    /// Run the debugger files saved on the instrument, uploading and saving only those
+    /// whose content hash doesn't match.
+    fn load_cached_debugger_files(&mut self) -> Result<()> {
+        let cached = self
+            .query_instrument(&CachedHashes::query(), CACHE_END)?
+            .and_then(|output| CachedHashes::parse(&output))
+            .unwrap_or_default();
+
+        for (name, resource, cached_hash) in [
+            (CACHED_TSPDBG, TSPDBG_TSP, cached.tspdbg),
+            (CACHED_KIDEBUGGER, KIDEBUGGER_TSP, cached.ki_debugger),
+        ] {
+            let source = minify(&resource.decrypt()?.to_string());
+            let hash = cache::content_hash(&source);
+            if cached_hash.as_deref() == Some(hash.as_str()) {
+                self.instrument
+                    .write_all(format!("{name}.run()\n").as_bytes())?;
+            } else {
+                self.instrument.write_script(
+                    name.as_bytes(),
+                    cache::tag(&source, &hash).as_bytes(),
+                    true,
+                    true,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Send a query to the instrument and read until `end` has been printed.
+    ///
+    /// Anything else the instrument printed in the meantime (e.g. errors from a
+    /// previous command) is passed on to the user.
+    ///
+    /// # Returns
+    /// The output of the instrument, or `None` if `end` wasn't printed in time.
+    fn query_instrument(&mut self, query: &str, end: &str) -> Result<Option<String>> {
+        self.instrument.write_all(query.as_bytes())?;
+
+        let output = match read_until(
+            &mut *self.instrument,
+            &[end.to_string()],
+            500,
+            Duration::from_millis(10),
+        ) {
+            Ok(output) => output,
+            Err(kic_lib::InstrumentError::Other(_)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        for line in output.lines().filter(|l| !l.contains(end)) {
+            self.println_flush(&line);
+        }
+
+        Ok(Some(output))
+    }
+
+    /// Query the version and capabilities of the debugger loaded on the instrument and
+    /// make sure it is the one that was just uploaded.
+    ///
+    /// # Errors
+    /// Returns [`DebugError::VersionMismatch`] if the instrument doesn't respond to the
+    /// handshake or reports a different debugger.
+    fn verify_debugger_version(&mut self) -> Result<()> {
+        let Some(output) = self.query_instrument(&DebuggerVersion::query(), HANDSHAKE_END)? else {
+            return Err(DebugError::VersionMismatch {
+                expected: crate::VERSION.to_string(),
+                found: "no response from kiDebugger".to_string(),
+            });
+        };
+
+        let Some(version) = DebuggerVersion::parse(&output) else {
+            return Err(DebugError::VersionMismatch {
+                expected: crate::VERSION.to_string(),
+                found: "an unreadable handshake response".to_string(),
+            });
+        };
+        version.verify()
+    }
+
     fn clear_debugger_file_sources(&mut self) -> Result<()> {
+        if self.cache_scripts {
+            // The cached copies need their source to be checked on the next session.
+            return Ok(());
+        }
         self.instrument
             .write_all(b"getmetatable(kiDebugger).Objects.source = nil\n")?;
         Ok(())
@@ -159,15 +687,30 @@ impl Debugger {
     /// Send the `KiSetWatchpoint` command to the on-instrument debugger
     /// * Arguments
     ///   `watch_point` - A WatchpointInfo struct holds watchpoint information
-    pub fn set_watchpoint(&mut self, watch_point: WatchpointInfo) -> Result<()> {
+    ///
+    /// # Errors
+    /// Returns a [`DebugError::CommandError`] if a SCPI or attribute watch entry could
+    /// have side effects, see [`WatchpointInfo::validate`]. IO Errors from writing to
+    /// the instrument may also occur.
+    pub fn set_watchpoint(&mut self, mut watch_point: WatchpointInfo) -> Result<()> {
+        // Send the expression that was validated.
+        watch_point.expression = watch_point.expression.trim().to_string();
+        watch_point.validate()?;
         let mut enable_val = 1;
         if !watch_point.enable {
             enable_val = 0;
         }
         // watch expressions need to be double-escaped because they will be executed as a string in Lua.
         let expression = watch_point.expression.replace('\"', "\\\"");
-        self.instrument
-            .write_all(format!("kiSetWatchpoint(\"{expression}\",{enable_val})\n").as_bytes())?;
+        let kind = watch_point
+            .kind()
+            .map_or_else(|| "nil".to_string(), |k| format!("\"{}\"", k.as_str()));
+        self.instrument.write_all(
+            format!("kiSetWatchpoint(\"{expression}\",{enable_val},{kind})\n").as_bytes(),
+        )?;
+        self.watchpoints
+            .retain(|w| w.expression != watch_point.expression);
+        self.watchpoints.push(watch_point);
 
         Ok(())
     }
@@ -197,39 +740,56 @@ impl Debugger {
     /// * Arguments
     /// * `var_info` - A VariableInfo struct holds variable information
     ///
-    /// * Example {"StackLevel":2,"ArgumentList":["x", "y", "z"],"Value":"7","Scope":"locals"}
-    /// * `var_info` : `{"StackLevel":0,"ArgumentList":["newTab", "tab", "x"],"Value":"7","Scope":"upvalues"}`
+    /// * Example {"StackLevel":2,"ArgumentList":["\"x\"", "\"y\"", "1"],"Value":"7","Scope":"locals"}
+    /// * `var_info` : `{"StackLevel":0,"ArgumentList":["\"newTab\"", "\"tab\"", "\"x\""],"Value":"7","Scope":"upvalues"}`
+    /// * the elements of ArgumentList are passed on as they are, names as quoted
+    ///   string literals and array indices as integers
     /// * possible values of Scope are "locals", "upvalues", "globals"
+    /// * possible values of the optional Type are "number", "string", "boolean", "nil"
+    ///   and "expression" (the default)
+    ///
+    /// # Errors
+    /// Returns a [`DebugError::CommandError`] if the value doesn't match its type or an
+    /// element of the ArgumentList is neither a quoted name nor an index, and
+    /// [`DebugError::VariableNotSet`] if a local, an upvalue or a global that isn't a
+    /// table field could not be set. IO Errors from writing to the instrument may
+    /// also occur.
     pub fn set_variable(&mut self, var_info: VariableInfo) -> Result<()> {
-        let mut el: String;
-        let mut index = 0;
-        let mut arg_list: String = "".to_string();
-        while index < var_info.argument_list.len() {
-            el = var_info.argument_list[index].to_owned();
-            arg_list = format!("{arg_list},{el}");
-            index += 1;
-        }
-        arg_list = arg_list.trim_start_matches([',', ' ']).to_string();
-
+        let arg_list = var_info.arguments()?;
         let level = var_info.stack_level.to_string();
-        let mut value = var_info.value.to_string();
-        value = value.replace('\"', "\\\"");
-        if var_info.scope_type == "locals" {
-            self.instrument.write_all(
-                format!("kiSetLocalVariable({level},\"{value}\",{arg_list})\n").as_bytes(),
-            )?;
-        }
-        if var_info.scope_type == "upvalues" {
-            self.instrument.write_all(
-                format!("kiSetUpVariable({level},\"{value}\",{arg_list})\n").as_bytes(),
-            )?;
-        }
-        if var_info.scope_type == "globals" {
-            self.instrument.write_all(
-                format!("kiSetGlobalVariable({level},\"{value}\", {arg_list})\n").as_bytes(),
-            )?;
+        let value = variable::lua_string_literal(&var_info.value()?.to_expression());
+        let scope = var_info.scope_type;
+        self.instrument
+            .write_all(format!("{}({level},{value},{arg_list})\n", scope.setter()).as_bytes())?;
+
+        // kiDebugger replies to a global table field without saying whether it was set.
+        let nested_global = scope == Scope::Globals && var_info.argument_list.len() > 1;
+
+        let element = scope.reply_element();
+        let reply = read_until(
+            &mut *self.instrument,
+            &[format!("</{element}>"), format!("<{element} />")],
+            200,
+            Duration::from_millis(10),
+        );
+        let set = match reply {
+            Ok(output) => {
+                // The reply is followed by the stack trace the front end is waiting for.
+                let frames = self.output.push(&format!("{output}\n"))?;
+                self.history.observe(&frames);
+                self.track_changes(frames)?;
+                variable::parse_reply(&output, element)
+            }
+            Err(kic_lib::InstrumentError::Other(_)) => None,
+            Err(e) => return Err(e.into()),
+        };
+        if set == Some(true) || nested_global {
+            Ok(())
+        } else {
+            Err(DebugError::VariableNotSet {
+                name: var_info.argument_list.join("."),
+            })
         }
-        Ok(())
     }
 
     /// Send the `kiClearBreakpoints()` command to the instrument
@@ -254,6 +814,100 @@ impl Debugger {
         Ok(())
     }
 
+    /// Pause the running debuggee at its next line outside of the debugger. The
+    /// instrument checks for the request at least once a second, so a debuggee waiting
+    /// in a single long instrument command pauses after it returns. Only a session made
+    /// pausable with [`Debugger::set_pausable`] can be paused.
+    /// # Errors
+    /// IO Errors from writing to the instrument may occur
+    pub fn pause_debugging(&mut self) -> Result<()> {
+        if self.script_source.is_none() {
+            self.println_flush(&"No script is being debugged".yellow());
+            return Ok(());
+        }
+        if !self.pausable {
+            self.println_flush(
+                &"The script can't be paused, start the session with --pausable".yellow(),
+            );
+            return Ok(());
+        }
+        self.instrument.write_all(b"kiPause()\n")?;
+        Ok(())
+    }
+
+    /// Show the stop before the shown one from the recorded history. The instrument
+    /// stays paused where it is.
+    /// # Errors
+    /// IO Errors from writing the output may occur
+    pub fn step_back(&mut self) -> Result<()> {
+        let stop = self.history.step_back().cloned();
+        self.show_recorded_stop(stop)
+    }
+
+    /// Show the latest earlier stop at an enabled breakpoint from the recorded history,
+    /// or the oldest recorded stop. The instrument stays paused where it is.
+    /// # Errors
+    /// IO Errors from writing the output may occur
+    pub fn reverse_continue(&mut self) -> Result<()> {
+        let breakpoints = self.breakpoint_lines();
+        let stop = self
+            .history
+            .reverse_continue(|line| breakpoints.contains(&line))
+            .cloned();
+        self.show_recorded_stop(stop)
+    }
+
+    /// While an earlier stop is shown, step or continue through the recorded history
+    /// instead of resuming the debuggee.
+    ///
+    /// # Returns
+    /// Whether the history was navigated.
+    fn replay_forward(&mut self, to_breakpoint: bool) -> Result<bool> {
+        if !self.history.is_replaying() {
+            return Ok(false);
+        }
+        let breakpoints = self.breakpoint_lines();
+        let stop = if to_breakpoint {
+            self.history
+                .continue_forward(|line| breakpoints.contains(&line))
+                .cloned()
+        } else {
+            self.history.step_forward().cloned()
+        };
+        self.show_recorded_stop(stop)?;
+        Ok(true)
+    }
+
+    fn breakpoint_lines(&self) -> Vec<u32> {
+        self.breakpoints
+            .iter()
+            .filter(|b| b.enable)
+            .map(|b| b.line_number)
+            .collect()
+    }
+
+    /// Send a recorded stop to the front end as if the debuggee had stopped there.
+    fn show_recorded_stop(&mut self, stop: Option<Stop>) -> Result<()> {
+        let Some(stop) = stop else {
+            self.println_flush(&"There is no earlier recorded stop.".yellow());
+            return Ok(());
+        };
+        self.output.write_console(&format!("{}\n", stop.frame))?;
+        self.output.write_console(&self.history.render_frame())?;
+        let line = stop.line.map_or_else(|| "?".to_string(), |l| l.to_string());
+        let (index, len) = self.history.position();
+        let message = if self.history.is_replaying() {
+            format!(
+                "History: stop {} of {len} at line {line}. The instrument is still paused at the latest stop.",
+                index + 1
+            )
+        } else {
+            format!("Back at the latest stop at line {line}.")
+        };
+        self.println_flush(&message.yellow());
+        Ok(())
+    }
+
     /// Send `kiStepOver` command to the instrument
     /// which will step over on the current line
     /// # Errors
@@ -289,59 +943,192 @@ impl Debugger {
         // If the session in progress, abort will terminate it,
         // if session already ended, abort will not do anything
         self.instrument.write_all(b"abort\n")?;
+        self.instrument.write_all(mocks::RESTORE_MOCKS.as_bytes())?;
         self.instrument.write_all(b"kiDebugger = nil\n")?;
 
-        if let Some(debug_file_name) = self.debuggee_file_name.to_owned() {
+        // Remove every debuggee script uploaded during this session by its real name.
+        for debug_file_name in std::mem::take(&mut self.script_names).into_values() {
             self.instrument
                 .write_all(format!("{debug_file_name} = nil\n").as_bytes())?;
             self.instrument
                 .write_all(format!("script.delete(\"{debug_file_name}\")\n").as_bytes())?;
-
-            self.debuggee_file_name = None;
+        }
+        self.debuggee_file_name = None;
+        if self.mocks_file.is_some() {
+            let name = mocks::MOCKS_SCRIPT_NAME;
+            self.instrument
+                .write_all(format!("{name} = nil\nscript.delete(\"{name}\")\n").as_bytes())?;
         }
 
-        // kiDebugger global functions.
-        let ki_debugger_global_functions = &[
-            "kiClearBreakpoints",
-            "kiSetBreakpoint",
-            "kiSetWatchpoint",
-            "kiClearWatchpoints",
-            "kiClearWatchpoint",
-            "kiExecuteWithDebugger",
-            "kiSetUpVariable",
-            "kiSetLocalVariable",
-            "kiSetGlobalVariable",
-        ];
-
-        for func in ki_debugger_global_functions {
+        for func in KI_DEBUGGER_GLOBAL_FUNCTIONS {
             self.instrument
                 .write_all(format!("{func} = nil\n").as_bytes())?;
         }
 
+        // The cached copies (`kic_kiDebugger` and `kic_tspdbg`) are saved under different
+        // names and are left on the instrument for the next session.
         self.instrument
             .write_all(b"script.delete(\"kiDebugger\")\n")?;
 
+        let cleaned = self.cleanup(false);
+
+        self.instrument.write_all(
+            b"if _kic_orig_prompts ~= nil then localnode.prompts = _kic_orig_prompts _kic_orig_prompts = nil end\n",
+        )?;
+
+        cleaned.map(|_| ())
+    }
+
+    /// Remove any debugger scripts, debuggee scripts and debugger globals that are
+    /// still on the instrument, e.g. after a session that crashed. The cached debugger
+    /// scripts are kept unless `remove_caches` is set. Once this succeeded, the
+    /// instrument isn't cleaned up again when the debugger is dropped.
+    ///
+    /// Removal is verified by querying the instrument again and retried up to
+    /// [`CLEANUP_ATTEMPTS`] times.
+    ///
+    /// # Returns
+    /// The names of the scripts and globals that were removed.
+    ///
+    /// # Errors
+    /// Returns [`DebugError::CleanupIncomplete`] if the instrument doesn't respond or
+    /// anything is still left after the last attempt.
+    pub fn cleanup(&mut self, remove_caches: bool) -> Result<Vec<String>> {
+        let keep: &[&str] = if remove_caches {
+            &[]
+        } else {
+            &[CACHED_TSPDBG, CACHED_KIDEBUGGER]
+        };
+
+        let mut removed = Vec::new();
+        for _ in 0..CLEANUP_ATTEMPTS {
+            let leftovers = self.query_leftovers()?.without(keep);
+            if leftovers.is_empty() {
+                self.cleaned_up = true;
+                return Ok(removed);
+            }
+            for command in leftovers.removal_commands() {
+                self.instrument.write_all(command.as_bytes())?;
+            }
+            for name in leftovers.names {
+                if !removed.contains(&name) {
+                    removed.push(name);
+                }
+            }
+        }
+
+        let leftovers = self.query_leftovers()?.without(keep);
+        if leftovers.is_empty() {
+            self.cleaned_up = true;
+            Ok(removed)
+        } else {
+            Err(DebugError::CleanupIncomplete {
+                details: format!("still present: {}", leftovers.names.join(", ")),
+            })
+        }
+    }
+
+    fn query_leftovers(&mut self) -> Result<Leftovers> {
+        self.query_instrument(&Leftovers::query(), CLEANUP_END)?
+            .and_then(|output| Leftovers::parse(&output))
+            .ok_or_else(|| DebugError::CleanupIncomplete {
+                details: "the instrument did not respond to the cleanup query".to_string(),
+            })
+    }
+
+    /// Reconnect to the instrument after the connection was lost and tell the user
+    /// whether the debuggee survived.
+    ///
+    /// # Errors
+    /// Returns [`DebugError::ConnectionLost`] if the instrument could not be reconnected.
+    fn recover_connection(&mut self) -> Result<()> {
+        let Some(policy) = self.reconnect.clone() else {
+            return Ok(());
+        };
+        self.println_flush(&"\nConnection to the instrument was lost, reconnecting...".yellow());
+        self.instrument = policy.reconnect()?;
+        self.instrument.set_nonblocking(true)?;
+
+        let status = self
+            .query_instrument(STATUS_QUERY, STATUS_END)?
+            .and_then(|output| DebuggeeStatus::parse(&output));
+        match status {
+            Some(DebuggeeStatus::Paused) => {
+                self.println_flush(&"Reconnected, the debuggee is still paused.".green());
+            }
+            Some(DebuggeeStatus::Running) => {
+                self.println_flush(&"Reconnected, the debuggee is still running.".green());
+            }
+            Some(DebuggeeStatus::Ended) => {
+                self.println_flush(&"Reconnected, but the debuggee is no longer running.".yellow());
+                if self.debuggee_file_path.is_some() {
+                    self.println_flush(
+                        &"Enter `.debug restart` to re-launch it with the same breakpoints."
+                            .yellow(),
+                    );
+                }
+            }
+            // Without pausing, a running debuggee only answers once it ended.
+            None => {
+                self.println_flush(
+                    &"Reconnected. The debugger didn't answer, the debuggee is probably still running."
+                        .yellow(),
+                );
+            }
+        }
         Ok(())
     }
 
     /// Start the Repl
     ///
+    /// A crash report is written if the session ends with an error from the instrument
+    /// connection.
+    ///
     /// # Errors
     /// There are many errors that can be returned from this function, they include but
     /// aren't limited to any errors possible from [`std::io::Read`] or [`std::io::Write`]
-    #[allow(clippy::too_many_lines)] //This is just going to be a long function
     pub fn start(&mut self) -> Result<()> {
+        self.instrument_info = self.instrument.info().ok();
+        let result = self.run_session();
+        if let Err(
+            e @ (DebugError::IOError { .. }
+            | DebugError::InstrumentError { .. }
+            | DebugError::ConnectionLost { .. }),
+        ) = &result
+        {
+            self.write_crash_report(&e.to_string(), Vec::new());
+        }
+        result
+    }
+
+    #[allow(clippy::too_many_lines)] //This is just going to be a long function
+    fn run_session(&mut self) -> Result<()> {
         // let mut prev_state: Option<ReadState> = None;
         // let mut state: Option<ReadState> = None;
         self.instrument.set_nonblocking(true)?;
 
         let (user_out, loop_in) = channel();
 
-        let join = Self::init_user_input(user_out)?;
+        if let Err(e) = Self::init_interrupt_handler(user_out.clone()) {
+            self.println_flush(&format!(
+                "Unable to handle Ctrl-C, the instrument will not be cleaned up if interrupted: {e}"
+            ));
+        }
+        let commands = self
+            .commands
+            .take()
+            .unwrap_or_else(|| Box::new(StdinSource));
+        let join = Self::init_user_input(user_out, commands)?;
+
+        // Keep the original setting so it can be restored in `exit_debugger()`.
+        self.instrument.write_all(
+            b"if _kic_orig_prompts == nil then _kic_orig_prompts = localnode.prompts end localnode.prompts = 0\n",
+        )?;
 
-        self.instrument.write_all(b"localnode.prompts = 0\n")?;
+        let mut interrupted = false;
+        let mut last_output = Instant::now();
 
-        Self::print_flush(&"\nTSP> ".blue())?;
+        self.print_flush(&"\nTSP> ".blue())?;
         'user_loop: loop {
             self.instrument.set_nonblocking(true)?;
             thread::sleep(Duration::from_millis(1));
@@ -349,114 +1136,239 @@ impl Debugger {
             let read_size = match self.instrument.read(&mut read_buf) {
                 Ok(read_size) => read_size,
                 Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => 0,
+                Err(e) if self.reconnect.is_some() && ReconnectPolicy::is_connection_lost(&e) => {
+                    self.recover_connection()?;
+                    0
+                }
                 Err(e) => return Err(e.into()),
             };
             let read_buf: Vec<u8> = read_buf[..read_size].into();
-            if !String::from_utf8_lossy(&read_buf)
-                .trim_end_matches(char::from(0))
-                .is_empty()
-            {
-                Self::print_flush(&String::from_utf8_lossy(&read_buf))?;
+            let mut read_text = std::mem::take(&mut self.unread_output);
+            read_text.push_str(String::from_utf8_lossy(&read_buf).trim_end_matches(char::from(0)));
+            let read_text = read_text.as_str();
+            if read_text.is_empty() {
+                // Don't hold back a line without a trailing newline forever.
+                if self.output.has_pending() && last_output.elapsed() > OUTPUT_FLUSH_DELAY {
+                    self.output.flush()?;
+                }
+            } else {
+                let frames = self.output.push(read_text)?;
+                self.history.observe(&frames);
+                if let Err(e) = self
+                    .report_events(&frames)
+                    .and_then(|()| self.track_changes(frames))
+                {
+                    self.recover_lost_connection(e)?;
+                }
+                last_output = Instant::now();
             }
 
             match loop_in.try_recv() {
-                Ok(req) => match req {
-                    Request::BreakPoint { breakpoint_info } => {
-                        self.set_breakpoint(&breakpoint_info)?;
-                    }
-                    Request::Watchpoint { watchpoint_info } => {
-                        self.set_watchpoint(watchpoint_info)?;
-                    }
-                    Request::Variable { vairable_info } => {
-                        self.set_variable(vairable_info)?;
-                    }
-                    Request::StartDebugger {
-                        file_path,
-                        break_points,
-                    } => {
-                        let file_path = Path::new(
-                            (file_path)
-                                .trim()
-                                .trim_end_matches(['\'', '"'])
-                                .trim_start_matches(['\'', '"']),
-                        );
-
-                        if let Ok(_file) = fs::File::open(file_path) {
-                            self.debuggee_file_path = Some(file_path.to_path_buf());
-                            let file_contents = fs::read_to_string(file_path)?;
-                            let script_name = file_path
-                                .file_stem()
-                                .unwrap()
-                                .to_os_string()
-                                .into_string()
-                                .unwrap()
-                                .replace(' ', "_");
-                            self.start_debugger(&script_name, &file_contents, break_points)?;
-                        } else {
-                            return Err(DebugError::IOError {
-                                source: Error::new(
-                                    std::io::ErrorKind::NotFound,
-                                    "Error: Could not locate file".to_string(),
-                                ),
-                            });
-                        }
-                    }
-                    Request::Run => {
-                        self.continue_debugging()?;
-                    }
-                    Request::StepOver => {
-                        self.stepover_debugging()?;
-                    }
-                    Request::ClearBreakPoints => {
-                        self.clear_breakpoints()?;
-                    }
-                    Request::StepIn => {
-                        self.stepin_debugging()?;
-                    }
-                    Request::StepOut => {
-                        self.stepout_debugging()?;
-                    }
-                    Request::Exit => {
-                        clear_output_queue(&mut *self.instrument, 5, Duration::from_millis(100))?;
+                Ok(req) => match self.handle_request(req) {
+                    Ok(Flow::Continue) => {}
+                    Ok(Flow::Exit) => break 'user_loop,
+                    Ok(Flow::Interrupt) => {
+                        interrupted = true;
                         break 'user_loop;
                     }
-                    Request::Restart => {
-                        eprintln!("RESTART RECV'D");
-                        self.instrument.write_all(b"abort\n")?;
-                        self.instrument.write_all(b"*RST\n")?;
-                        std::thread::sleep(Duration::from_millis(100));
-                        let orig_file_name = self
-                            .debuggee_file_name
-                            .clone()
-                            .expect("should have file name in Debugger App");
-                        let orig_file_path = self
-                            .debuggee_file_path
-                            .clone()
-                            .expect("should have file path in Debugger App");
-                        let orig_breakpoints = self.breakpoints.clone();
-                        if let Ok(_file) = fs::File::open(&orig_file_path) {
-                            let file_contents = fs::read_to_string(&orig_file_path)?;
-                            self.start_debugger(&orig_file_name, &file_contents, orig_breakpoints)?;
-                        }
-                    }
-                    Request::GetError(error) => {
-                        Self::println_flush(&format!("Error: {error:?}"));
-                    }
-
-                    Request::Tsp(tsp) => {
-                        self.instrument.write_all(format!("{tsp}\n").as_bytes())?;
-                    }
-                    _ => {}
+                    Err(e) => self.recover_lost_connection(e)?,
                 },
                 Err(TryRecvError::Disconnected) => break 'user_loop,
                 Err(TryRecvError::Empty) => {}
             }
         }
         drop(loop_in);
-        let _ = join.join();
+        // The user-input thread is still blocked reading commands after an interrupt, so it
+        // is left to end with the process.
+        if !interrupted {
+            let _ = join.join();
+        }
         Ok(())
     }
 
+    /// Carry out a request of the user.
+    ///
+    /// # Returns
+    /// How the session goes on.
+    ///
+    /// # Errors
+    /// Returns [`DebugError::CommandError`] and similar errors of the request, and
+    /// IO errors from the instrument.
+    #[allow(clippy::too_many_lines)]
+    fn handle_request(&mut self, req: Request) -> Result<Flow> {
+        match req {
+            Request::BreakPoint { breakpoint_info } => {
+                self.set_breakpoint(&breakpoint_info)?;
+            }
+            Request::Watchpoint { watchpoint_info } => match self.set_watchpoint(watchpoint_info) {
+                Err(e @ DebugError::CommandError { .. }) => {
+                    self.println_flush(&format!("Error: {e}"));
+                }
+                result => result?,
+            },
+            Request::Variable { vairable_info } => {
+                if let Err(e) = self.set_variable(vairable_info) {
+                    self.println_flush(&format!("Error: {e}"));
+                }
+            }
+            Request::StartDebugger {
+                file_path,
+                break_points,
+                source: Some(source),
+            } => {
+                let file_path = PathBuf::from(file_path);
+                let script_name = file_path
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().replace(' ', "_"))
+                    .unwrap_or_default();
+                self.debuggee_file_path = Some(file_path);
+                self.debuggee_source = Some(source.clone());
+                self.start_debugger(&script_name, &source, break_points)?;
+            }
+            Request::StartDebugger {
+                file_path,
+                break_points,
+                source: None,
+            } => {
+                self.debuggee_source = None;
+                let file_path = Path::new(
+                    (file_path)
+                        .trim()
+                        .trim_end_matches(['\'', '"'])
+                        .trim_start_matches(['\'', '"']),
+                );
+
+                if let Ok(_file) = fs::File::open(file_path) {
+                    self.debuggee_file_path = Some(file_path.to_path_buf());
+                    let file_contents = fs::read_to_string(file_path)?;
+                    let script_name = file_path
+                        .file_stem()
+                        .unwrap()
+                        .to_os_string()
+                        .into_string()
+                        .unwrap()
+                        .replace(' ', "_");
+                    self.start_debugger(&script_name, &file_contents, break_points)?;
+                } else {
+                    return Err(DebugError::IOError {
+                        source: Error::new(
+                            std::io::ErrorKind::NotFound,
+                            "Error: Could not locate file".to_string(),
+                        ),
+                    });
+                }
+            }
+            Request::AttachScript { name } => {
+                if let Err(e) = self.attach_script(&name) {
+                    self.println_flush(&format!("Error: {e}"));
+                }
+            }
+            Request::Run => {
+                if !self.replay_forward(true)? {
+                    self.continue_debugging()?;
+                }
+            }
+            Request::Pause => {
+                self.pause_debugging()?;
+            }
+            Request::StepOver => {
+                if !self.replay_forward(false)? {
+                    self.stepover_debugging()?;
+                }
+            }
+            Request::ClearBreakPoints => {
+                self.clear_breakpoints()?;
+            }
+            Request::StepIn => {
+                if !self.replay_forward(false)? {
+                    self.stepin_debugging()?;
+                }
+            }
+            Request::StepOut => {
+                if !self.replay_forward(false)? {
+                    self.stepout_debugging()?;
+                }
+            }
+            Request::Log { action } => match self.manage_log(action) {
+                Err(e @ DebugError::CommandError { .. }) => {
+                    self.println_flush(&format!("Error: {e}"));
+                }
+                result => result?,
+            },
+            Request::StepBack => {
+                self.step_back()?;
+            }
+            Request::ReverseContinue => {
+                self.reverse_continue()?;
+            }
+            Request::Exit => {
+                clear_output_queue(&mut *self.instrument, 5, Duration::from_millis(100))?;
+                return Ok(Flow::Exit);
+            }
+            Request::Interrupt => {
+                self.instrument.write_all(b"abort\n")?;
+                return Ok(Flow::Interrupt);
+            }
+            Request::Restart => {
+                eprintln!("RESTART RECV'D");
+                self.instrument.write_all(b"abort\n")?;
+                self.instrument.write_all(b"*RST\n")?;
+                std::thread::sleep(Duration::from_millis(100));
+                if let Some(name) = self.attached_script.clone() {
+                    // Like `.debug attach`, a script that can't be attached again doesn't
+                    // end the session.
+                    if let Err(e) = self.attach_script(&name) {
+                        self.println_flush(&format!("Error: {e}"));
+                    }
+                    return Ok(Flow::Continue);
+                }
+                let orig_file_path = self
+                    .debuggee_file_path
+                    .clone()
+                    .expect("should have file path in Debugger App");
+                // `debuggee_file_name` is the name on the instrument, so start
+                // again from the file name.
+                let orig_file_name = orig_file_path
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().replace(' ', "_"))
+                    .unwrap_or_default();
+                let orig_breakpoints = self.breakpoints.clone();
+                if let Some(source) = self.debuggee_source.clone() {
+                    self.start_debugger(&orig_file_name, &source, orig_breakpoints)?;
+                } else if let Ok(_file) = fs::File::open(&orig_file_path) {
+                    let file_contents = fs::read_to_string(&orig_file_path)?;
+                    self.start_debugger(&orig_file_name, &file_contents, orig_breakpoints)?;
+                }
+            }
+            Request::GetError(error) => {
+                self.println_flush(&format!("Error: {error:?}"));
+            }
+
+            Request::Tsp(tsp) => {
+                self.instrument.write_all(format!("{tsp}\n").as_bytes())?;
+            }
+            _ => {}
+        }
+        Ok(Flow::Continue)
+    }
+
+    /// Reconnect if `error` is from a lost connection to the instrument and the session
+    /// can reconnect. The command that failed is not sent again.
+    ///
+    /// # Errors
+    /// Returns `error` if it isn't from a lost connection, or the error of reconnecting.
+    fn recover_lost_connection(&mut self, error: DebugError) -> Result<()> {
+        match error {
+            DebugError::IOError { source }
+                if self.reconnect.is_some() && ReconnectPolicy::is_connection_lost(&source) =>
+            {
+                self.recover_connection()?;
+                self.println_flush(&"The last command was not sent, enter it again.".yellow());
+                Ok(())
+            }
+            e => Err(e),
+        }
+    }
     /// Command Line Interface
     #[allow(clippy::cognitive_complexity)]
     fn cli() -> Command {
@@ -476,6 +1388,11 @@ impl Debugger {
                             .about("Continue to next breakpont")
                             .disable_help_flag(true),
                     )
+                    .subcommand(
+                        Command::new("pause")
+                            .about("Pause the running script at its next line")
+                            .disable_help_flag(true),
+                    )
                     .subcommand(
                         Command::new("stepOver")
                             .about("Step-over")
@@ -491,6 +1408,16 @@ impl Debugger {
                             .about("Step-out")
                             .disable_help_flag(true),
                     )
+                    .subcommand(
+                        Command::new("stepBack")
+                            .about("Show the previous recorded stop")
+                            .disable_help_flag(true),
+                    )
+                    .subcommand(
+                        Command::new("reverseContinue")
+                            .about("Show the previous recorded stop at a breakpoint")
+                            .disable_help_flag(true),
+                    )
                     .subcommand(
                         Command::new("exit")
                             .about("exit debugger and disconnect instrument")
@@ -521,6 +1448,22 @@ impl Debugger {
                             .arg(arg!([Variable]).value_parser(value_parser!(String)))
                             .disable_help_flag(true),
                     )
+                    .subcommand(
+                        Command::new("attachScript")
+                            .about("debug a script saved on the instrument")
+                            .disable_help_flag(true)
+                            .arg(arg!([Name]).value_parser(value_parser!(String))),
+                    )
+                    .subcommand(
+                        Command::new("log")
+                            .about("switch the on-instrument debugger log on or off, or fetch and clear it")
+                            .disable_help_flag(true)
+                            .arg(
+                                arg!([Action])
+                                    .value_parser(value_parser!(LogAction))
+                                    .required(true),
+                            ),
+                    )
                     .subcommand(
                         Command::new("restart")
                             .about("restart the debugger")
@@ -530,15 +1473,18 @@ impl Debugger {
             .disable_help_flag(true)
     }
 
-    /// Start a thread that blocks on user input lines, converts them to the proper request
-    /// and `send()`s them on the `out` channel.
+    /// Start a thread that blocks on user input lines from `commands`, converts them to
+    /// the proper request and `send()`s them on the `out` channel.
     ///
     /// # Return
     /// This function returns a join handle to the created user-input thread.
     ///
     /// # Errors
     /// This function can error if the thread couldn't be created.
-    fn init_user_input(out: Sender<Request>) -> Result<JoinHandle<Result<()>>> {
+    fn init_user_input(
+        out: Sender<Request>,
+        mut commands: Box<dyn CommandSource>,
+    ) -> Result<JoinHandle<Result<()>>> {
         let jh = std::thread::Builder::new()
             .name("user_input".to_string())
             .spawn(move || {
@@ -548,8 +1494,19 @@ impl Debugger {
                     //       if the caller of this function doesn't close the Sender or send
                     //       a message quickly enough.
                     let mut input = String::new();
-                    let _ = std::io::stdin().read_line(&mut input)?;
-                    let req = Self::parse_user_commands(&input)?;
+                    let _ = commands.read_line(&mut input)?;
+                    let mut req = Self::parse_user_commands(&input)?;
+                    if let Request::StartDebugger {
+                        file_path,
+                        source: source @ None,
+                        ..
+                    } = &mut req
+                    {
+                        if file_path.trim() == source::STDIN_FILE_NAME {
+                            *source = Some(source::read_stdin_source(&mut *commands)?);
+                            *file_path = source::STDIN_VIRTUAL_FILE_NAME.to_string();
+                        }
+                    }
                     match out.send(req.clone()) {
                         Ok(()) => {}
                         Err(SendError(_)) => break 'input_loop,
@@ -565,6 +1522,50 @@ impl Debugger {
         Ok(jh)
     }
 
+    /// Install a Ctrl-C handler that `send()`s a [`Request::Interrupt`] on the `out`
+    /// channel so that the session ends and the debugger is removed from the instrument.
+    /// A second Ctrl-C within [`FORCE_EXIT_WINDOW`] exits immediately.
+    ///
+    /// The handler is installed once per process and interrupts every session that
+    /// registered with it, e.g. all instruments of a [`rack::Rack`].
+    ///
+    /// # Errors
+    /// This function can error if another Ctrl-C handler has already been installed.
+    fn init_interrupt_handler(out: Sender<Request>) -> Result<()> {
+        static TARGETS: Mutex<Vec<Sender<Request>>> = Mutex::new(Vec::new());
+        static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+        TARGETS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(out);
+        if INSTALLED.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let mut last_interrupt: Option<Instant> = None;
+        ctrlc::set_handler(move || {
+            if last_interrupt.is_some_and(|t| t.elapsed() < FORCE_EXIT_WINDOW) {
+                eprintln!(
+                    "{}",
+                    "Forced exit, the instrument was not cleaned up.".red()
+                );
+                std::process::exit(130);
+            }
+            last_interrupt = Some(Instant::now());
+            eprintln!(
+                "{}",
+                "Interrupted, cleaning up the instrument. Press Ctrl-C again to force exit."
+                    .yellow()
+            );
+            // Sessions that are already over are cleaning up or done.
+            TARGETS
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .retain(|out| out.send(Request::Interrupt).is_ok());
+        })
+        .map_err(|e| DebugError::Other(e.to_string()))
+    }
+
     /// Parse user command
     /// * `input` - A &str holds user input from the terminal
     /// * Return - A Result of Request
@@ -603,12 +1604,29 @@ impl Debugger {
         match matches {
             Some((".debug", flag)) => match flag.subcommand() {
                 Some(("run", _)) => Ok(Request::Run),
+                Some(("pause", _)) => Ok(Request::Pause),
                 Some(("stepOver", _)) => Ok(Request::StepOver),
                 Some(("stepIn", _)) => Ok(Request::StepIn),
                 Some(("stepOut", _)) => Ok(Request::StepOut),
+                Some(("stepBack", _)) => Ok(Request::StepBack),
+                Some(("reverseContinue", _)) => Ok(Request::ReverseContinue),
                 Some(("exit", _)) => Ok(Request::Exit),
                 Some(("clearBreakpoints", _)) => Ok(Request::ClearBreakPoints),
                 Some(("restart", _)) => Ok(Request::Restart),
+                Some(("log", flag)) => match flag.get_one::<LogAction>("Action") {
+                    Some(action) => Ok(Request::Log { action: *action }),
+                    None => Ok(Request::GetError(
+                        "Error: Could not find log command argument".to_string(),
+                    )),
+                },
+                Some(("attachScript", flag)) => match flag.get_one::<String>("Name") {
+                    Some(name) => Ok(Request::AttachScript {
+                        name: name.to_string(),
+                    }),
+                    None => Ok(Request::GetError(
+                        "Error: Could not find attachScript command argument".to_string(),
+                    )),
+                },
                 Some(("setBreakpoint", flag)) => {
                     let breakpoint_info = flag.get_one::<String>("Breakpoint"); //matches.get_one::<PathBuf>("config")
                     match breakpoint_info {
@@ -619,10 +1637,9 @@ impl Debugger {
                                 Ok(bp) => Ok(Request::BreakPoint {
                                     breakpoint_info: bp,
                                 }),
-                                Err(e) => {
-                                    Self::println_flush(&format!("serde error: {e:?}"));
-                                    Ok(Request::GetError(e.to_string()))
-                                }
+                                // The session prints this with `println_flush`, so it
+                                // reaches the front end like its other output.
+                                Err(e) => Ok(Request::GetError(format!("serde error: {e:?}"))),
                             }
                         }
                         _ => Ok(Request::GetError(
@@ -673,8 +1690,13 @@ impl Debugger {
 
                             match di {
                                 Ok(di) => Ok(Request::StartDebugger {
-                                    file_path: di.file_name,
+                                    file_path: if di.source.is_some() {
+                                        source::virtual_file_name(&di.file_name)
+                                    } else {
+                                        di.file_name
+                                    },
                                     break_points: di.break_points,
+                                    source: di.source,
                                 }),
                                 Err(e) => Ok(Request::GetError(e.to_string())),
                             }
@@ -693,9 +1715,332 @@ impl Debugger {
 
 impl Drop for Debugger {
     fn drop(&mut self) {
-        // if we get an Err(...) back, just ignore it.
-        // We can't do anything about it in the drop() anyway.
-        let _ = self.exit_debugger();
+        if self.cleaned_up {
+            return;
+        }
+        // We can't do anything about an Err(...) in the drop(), so just report it.
+        if let Err(e) = self.exit_debugger() {
+            eprintln!("{}", e.to_string().red());
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use std::{
+        fs,
+        io::{self, Write},
+        sync::{
+            mpsc::{channel, Receiver, Sender},
+            Arc, Mutex,
+        },
+        thread::{self, JoinHandle},
+        time::{Duration, Instant},
+    };
+
+    use kic_lib::{instrument::read_until, interface::NonBlock, model::simulator};
+
+    use super::{frontend::CommandSource, Debugger};
+    use crate::error::Result;
+
+    struct Commands(Receiver<String>);
+
+    impl CommandSource for Commands {
+        fn read_line(&mut self, buf: &mut String) -> io::Result<usize> {
+            let line = self.0.recv().unwrap_or_default();
+            buf.push_str(&line);
+            Ok(line.len())
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Output {
+        /// Wait for `text` in the output after `from` and return where it ends.
+        fn wait_for(&self, text: &str, from: usize) -> usize {
+            let start = Instant::now();
+            loop {
+                let output = String::from_utf8_lossy(&self.0.lock().unwrap()).to_string();
+                if let Some(at) = output.get(from..).and_then(|o| o.find(text)) {
+                    return from + at + text.len();
+                }
+                assert!(
+                    start.elapsed() < Duration::from_secs(30),
+                    "no '{text}' in {}",
+                    &output[from.min(output.len())..]
+                );
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+
+    /// Start a debug session on a simulator, set up by `configure`, that reads its
+    /// commands from the returned sender.
+    fn simulator_session(
+        configure: impl FnOnce(&mut Debugger) + Send + 'static,
+    ) -> (Sender<String>, Output, JoinHandle<Result<()>>) {
+        simulator_session_then(configure, |_, result| result)
+    }
+
+    /// Like [`simulator_session`], and hands the debugger and the result of the session
+    /// to `after` once the session ended.
+    fn simulator_session_then<T: Send + 'static>(
+        configure: impl FnOnce(&mut Debugger) + Send + 'static,
+        after: impl FnOnce(&mut Debugger, Result<()>) -> T + Send + 'static,
+    ) -> (Sender<String>, Output, JoinHandle<T>) {
+        let (send, commands) = channel();
+        let output = Output::default();
+        let session_output = output.clone();
+        let session = thread::spawn(move || {
+            let mut instrument = simulator::Instrument::new().unwrap();
+            instrument.set_nonblocking(true).unwrap();
+            let mut debugger = Debugger::new(Box::new(instrument));
+            configure(&mut debugger);
+            debugger.set_front_end(Box::new(Commands(commands)), Box::new(session_output));
+            let result = debugger.start();
+            after(&mut debugger, result)
+        });
+        (send, output, session)
+    }
+
+    /// Exit the session and check that it ended without an error.
+    fn exit(send: Sender<String>, session: JoinHandle<Result<()>>) {
+        send.send(".debug exit\n".to_string()).unwrap();
+        drop(send);
+        assert!(session.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn breakpoints_and_steps_on_the_simulator() {
+        let (send, output, session) = simulator_session(|_| {});
+
+        let info = serde_json::json!({
+            "FileName": "steps.tsp",
+            "Source": "x = 1\ny = x + 1\nz = y + 1\nprint(\"z is\", z)\n",
+            "BreakPoints": [{ "LineNumber": 2, "Enable": true, "Condition": "" }],
+        });
+        send.send(format!(".debug '{info}'\n")).unwrap();
+        let at = output.wait_for("<breakpoint line='2' />", 0);
+        let at = output.wait_for("<debug-prompt />", at);
+
+        send.send(
+            ".debug setWatchpoint '{\"Enable\":true,\"Expression\":\"x + 1\"}'\n".to_string(),
+        )
+        .unwrap();
+        send.send(".debug stepOver\n".to_string()).unwrap();
+        let at = output.wait_for("<resume-stepover />", at);
+        let at = output.wait_for("currentline='3'", at);
+        let at = output.wait_for(
+            "<watchpoint expression='x + 1' value='2' type='number' />",
+            at,
+        );
+
+        send.send(".debug run\n".to_string()).unwrap();
+        let at = output.wait_for("z is\t3", at);
+        output.wait_for("<session-end />", at);
+
+        exit(send, session);
+    }
+
+    #[test]
+    fn variables_are_set_on_the_simulator() {
+        let (send, output, session) = simulator_session(|_| {});
+
+        let info = serde_json::json!({
+            "FileName": "nested.tsp",
+            "Source": "g = 1\nlocal t = {1, {a = 2}}\nprint(\"t is\", t[1], t[2].a, g)\n",
+            "BreakPoints": [{ "LineNumber": 3, "Enable": true, "Condition": "" }],
+        });
+        send.send(format!(".debug '{info}'\n")).unwrap();
+        let at = output.wait_for("<breakpoint line='3' />", 0);
+        let at = output.wait_for("<debug-prompt />", at);
+
+        let set = |path: &str, value: &str, scope: &str| {
+            format!(
+                ".debug setVariable '{{\"StackLevel\":0,\"ArgumentList\":{path},\"Value\":\"{value}\",\"Scope\":\"{scope}\"}}'\n"
+            )
+        };
+        send.send(set(r#"["\"t\"","1"]"#, "7", "locals")).unwrap();
+        send.send(set(r#"["\"t\"","2","\"a\""]"#, "5", "locals"))
+            .unwrap();
+        send.send(set(r#"["\"g\""]"#, "9", "globals")).unwrap();
+        send.send(set(r#"["\"missing\""]"#, "9", "globals"))
+            .unwrap();
+        let at = output.wait_for("Error: variable '\"missing\"' could not be set", at);
+        send.send(set(r#"["t"]"#, "9", "locals")).unwrap();
+        let at = output.wait_for("'t' is not a quoted name or an index", at);
+
+        send.send(".debug run\n".to_string()).unwrap();
+        let at = output.wait_for("t is\t7\t5\t9", at);
+        output.wait_for("<session-end />", at);
+
+        exit(send, session);
+    }
+
+    #[test]
+    fn instrument_errors_break_on_the_simulator() {
+        let (send, output, session) =
+            simulator_session(|debugger| debugger.set_break_on_instrument_error(true));
+
+        let info = serde_json::json!({
+            "FileName": "errors.tsp",
+            "Source": "x = 1\nsimulator.queueerror(-222, \"Parameter data out of range\")\ny = 2\nprint(\"y is\", y)\n",
+            "BreakPoints": [],
+        });
+        send.send(format!(".debug '{info}'\n")).unwrap();
+        let at = output.wait_for("<instrument-error line='3' code='-222'", 0);
+        let at = output.wait_for("<suspend-instrument-error line='3' count='1'", at);
+        let at = output.wait_for("<debug-prompt />", at);
+
+        send.send(".debug run\n".to_string()).unwrap();
+        let at = output.wait_for("y is\t2", at);
+        output.wait_for("<session-end />", at);
+
+        exit(send, session);
+    }
+
+    #[test]
+    fn instrument_errors_on_the_last_line_break_on_the_simulator() {
+        let (send, output, session) =
+            simulator_session(|debugger| debugger.set_break_on_instrument_error(true));
+
+        let info = serde_json::json!({
+            "FileName": "last.tsp",
+            "Source": "x = 1\nsimulator.queueerror(-222, \"Parameter data out of range\")\n",
+            "BreakPoints": [],
+        });
+        send.send(format!(".debug '{info}'\n")).unwrap();
+        let at = output.wait_for("<instrument-error line='2' code='-222'", 0);
+        let at = output.wait_for("<suspend-instrument-error line='2' count='1'", at);
+        let at = output.wait_for("<debug-prompt />", at);
+
+        send.send(".debug run\n".to_string()).unwrap();
+        output.wait_for("<session-end />", at);
+
+        exit(send, session);
+    }
+
+    #[test]
+    fn pauses_and_runs_deferred_commands_on_the_simulator() {
+        let (send, output, session) = simulator_session(|debugger| debugger.set_pausable(true));
+
+        let info = serde_json::json!({
+            "FileName": "loop.tsp",
+            "Source": "print(\"started\")\nn = 0\nwhile true do\n    n = n + 1\nend\n",
+            "BreakPoints": [],
+        });
+        send.send(format!(".debug '{info}'\n")).unwrap();
+        let at = output.wait_for("started", 0);
+
+        // Sent while the debuggee runs, so it is held until the pause.
+        send.send("print(\"deferred\")\n".to_string()).unwrap();
+        send.send(".debug pause\n".to_string()).unwrap();
+        let at = output.wait_for("<suspend-pause line='", at);
+        output.wait_for("deferred", at);
+
+        exit(send, session);
+    }
+
+    #[test]
+    fn only_pausable_sessions_pause_on_the_simulator() {
+        let (send, output, session) = simulator_session(|_| {});
+
+        let info = serde_json::json!({
+            "FileName": "loop.tsp",
+            "Source": "print(\"started\")\nfor i = 1, 20000 do\n    n = i\nend\nprint(\"finished\", n)\n",
+            "BreakPoints": [],
+        });
+        send.send(format!(".debug '{info}'\n")).unwrap();
+        let at = output.wait_for("started", 0);
+
+        send.send(".debug pause\n".to_string()).unwrap();
+        output.wait_for("start the session with --pausable", at);
+        let at = output.wait_for("finished\t20000", at);
+        output.wait_for("<session-end />", at);
+
+        exit(send, session);
+    }
+
+    #[test]
+    fn the_debuggee_status_is_reported_on_the_simulator() {
+        let (send, output, session) = simulator_session(|debugger| debugger.set_pausable(true));
+
+        let info = serde_json::json!({
+            "FileName": "status.tsp",
+            "Source": "x = 1\nfor i = 1, 100000 do\n    if i == 2 then print(\"loop\" .. \"ing\") end\nend\n",
+            "BreakPoints": [{ "LineNumber": 1, "Enable": true, "Condition": "" }],
+        });
+        send.send(format!(".debug '{info}'\n")).unwrap();
+        let at = output.wait_for("<breakpoint line='1' />", 0);
+        send.send("kiStatus()\n".to_string()).unwrap();
+        let at = output.wait_for("<debugger-status >paused</debugger-status>", at);
+
+        send.send(".debug run\n".to_string()).unwrap();
+        let at = output.wait_for("looping", at);
+        send.send("kiStatus()\n".to_string()).unwrap();
+        let at = output.wait_for("<debugger-status >running</debugger-status>", at);
+
+        let at = output.wait_for("<session-end />", at);
+        send.send("kiStatus()\n".to_string()).unwrap();
+        output.wait_for("<debugger-status >ended</debugger-status>", at);
+
+        exit(send, session);
+    }
+
+    #[test]
+    fn mocks_are_restored_on_the_simulator() {
+        let mocks = std::env::temp_dir().join(format!("kic-mocks-test-{}.lua", std::process::id()));
+        fs::write(
+            &mocks,
+            "kicTestDelay = function() end\nmock(\"delay\", kicTestDelay)\nmock(\"localnode.serialno\", \"mocked\")\n",
+        )
+        .unwrap();
+        let mocks_file = mocks.clone();
+        let (send, output, session) = simulator_session_then(
+            move |debugger| debugger.set_mocks_file(mocks_file),
+            |debugger, result| {
+                result?;
+                // What dropping the debugger does after `.debug exit`
+                debugger.exit_debugger()?;
+                debugger.instrument.write_all(
+                    b"print(\"restored\", delay ~= kicTestDelay, localnode.serialno ~= \"mocked\")\n",
+                )?;
+                Ok(read_until(
+                    &mut *debugger.instrument,
+                    &["restored".to_string()],
+                    100,
+                    Duration::from_millis(10),
+                )?)
+            },
+        );
+
+        let info = serde_json::json!({
+            "FileName": "mocked.tsp",
+            "Source": "delay(1000)\nprint(\"serial is\", localnode.serialno)\n",
+            "BreakPoints": [],
+        });
+        send.send(format!(".debug '{info}'\n")).unwrap();
+        let at = output.wait_for("serial is\tmocked", 0);
+        output.wait_for("<session-end />", at);
+
+        send.send(".debug exit\n".to_string()).unwrap();
+        drop(send);
+        let restored: Result<String> = session.join().unwrap();
+        fs::remove_file(&mocks).unwrap();
+        let restored = restored.unwrap();
+        assert!(restored.contains("restored\ttrue\ttrue"), "{restored}");
     }
 }
 
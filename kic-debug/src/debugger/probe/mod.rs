@@ -0,0 +1,134 @@
+//! Check that the connected instrument can be debugged before anything is uploaded,
+//! instead of failing with a Lua error once `kiDebugger` runs.
+use kic_lib::instrument::info::InstrumentInfo;
+
+use crate::error::{DebugError, Result};
+
+/// Marks the start of the probe response printed by the instrument.
+pub const PROBE_BEGIN: &str = "<kic-debugger-probe>";
+/// Marks the end of the probe response printed by the instrument.
+pub const PROBE_END: &str = "</kic-debugger-probe>";
+
+/// The instrument functions and objects the debugger relies on.
+pub const REQUIRED_PRIMITIVES: &[&str] = &[
+    "ki.remotecomm",
+    "ki.remotecomm.getmessage",
+    "ki.aborthook",
+    "debug.sethook",
+    "debug.getinfo",
+    "debug.getlocal",
+    "debug.setlocal",
+    "debug.getupvalue",
+    "debug.setupvalue",
+    "debug.traceback",
+    "loadstring",
+    "getfenv",
+    "setfenv",
+];
+
+/// The TSP command that makes the instrument print the missing primitives.
+#[must_use]
+pub fn query() -> String {
+    let primitives = REQUIRED_PRIMITIVES
+        .iter()
+        .map(|p| format!("\"{p}\""))
+        .collect::<Vec<_>>()
+        .join(",");
+    format!(
+        "do local m = {{}} for _, p in ipairs({{{primitives}}}) do local ok, v = pcall(function() local v = _G for n in string.gfind(p, \"[^.]+\") do v = v[n] end return v end) if not ok or v == nil then table.insert(m, p) end end print(\"{PROBE_BEGIN}\" .. table.concat(m, \",\") .. \"{PROBE_END}\") end\n"
+    )
+}
+
+/// Find and parse the probe response in the given instrument output.
+///
+/// # Returns
+/// The missing primitives, or `None` if the output doesn't contain a complete probe
+/// response.
+#[must_use]
+pub fn parse(output: &str) -> Option<Vec<String>> {
+    let end = output.rfind(PROBE_END)?;
+    let begin = output[..end].rfind(PROBE_BEGIN)?;
+    let response = &output[begin.checked_add(PROBE_BEGIN.len())?..end];
+    Some(
+        response
+            .split(',')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(ToString::to_string)
+            .collect(),
+    )
+}
+
+/// Check the primitives that are missing on the instrument.
+///
+/// # Errors
+/// Returns [`DebugError::UnsupportedFirmware`] if any primitives are missing, or if
+/// `missing` is `None` because the instrument didn't respond to the probe.
+pub fn verify(info: Option<&InstrumentInfo>, missing: Option<&[String]>) -> Result<()> {
+    let reason = match missing {
+        None => "it did not respond to the probe for the debug primitives".to_string(),
+        Some(missing) if !missing.is_empty() => format!("{} not available", missing.join(", ")),
+        Some(_) => return Ok(()),
+    };
+    Err(DebugError::UnsupportedFirmware {
+        model: info.map_or_else(|| "instrument".to_string(), |i| i.model.to_string()),
+        firmware: info
+            .and_then(|i| i.firmware_rev.as_deref())
+            .unwrap_or("unknown")
+            .to_string(),
+        reason,
+    })
+}
+
+#[cfg(test)]
+mod unit {
+    use kic_lib::{
+        instrument::info::InstrumentInfo,
+        model::{Model, Vendor},
+    };
+
+    use super::{parse, verify, PROBE_BEGIN, PROBE_END};
+    use crate::error::DebugError;
+
+    fn info(model: Model, firmware: &str) -> InstrumentInfo {
+        InstrumentInfo {
+            vendor: Vendor::Keithley,
+            model,
+            serial_number: "04331961".to_string(),
+            firmware_rev: Some(firmware.to_string()),
+        }
+    }
+
+    #[test]
+    fn probe_response_is_parsed() {
+        let output = format!("TSP>\n{PROBE_BEGIN}ki.aborthook,debug.sethook{PROBE_END}\n");
+
+        assert_eq!(
+            parse(&output),
+            Some(vec![
+                "ki.aborthook".to_string(),
+                "debug.sethook".to_string()
+            ])
+        );
+        assert_eq!(
+            parse(&format!("{PROBE_BEGIN}{PROBE_END}")),
+            Some(Vec::new())
+        );
+        assert_eq!(parse(PROBE_BEGIN), None);
+    }
+
+    #[test]
+    fn unsupported_instruments_are_rejected() {
+        assert!(verify(Some(&info(Model::_2601A, "2.2.6")), Some(&[])).is_ok());
+        assert!(verify(None, Some(&[])).is_ok());
+        assert!(matches!(
+            verify(Some(&info(Model::_2461, "1.7.12b")), Some(&["ki.aborthook".to_string()])),
+            Err(DebugError::UnsupportedFirmware { model, reason, .. })
+                if model == "2461" && reason.contains("ki.aborthook")
+        ));
+        assert!(matches!(
+            verify(Some(&info(Model::_2461, "1.7.12b")), None),
+            Err(DebugError::UnsupportedFirmware { reason, .. }) if reason.contains("did not respond")
+        ));
+    }
+}
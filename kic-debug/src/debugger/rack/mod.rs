@@ -0,0 +1,335 @@
+//! Debug sessions on several instruments at once from one console, e.g. two 2450s in a
+//! test rack.
+//!
+//! Every instrument gets its own [`Debugger`] on its own thread. Commands typed on the
+//! shared console go to every instrument, unless they start with `@<name>` to address
+//! one of them. The output of every instrument is shown line by line with its name in
+//! front. `.debug pauseAll` and `.debug continueAll` pause and continue the debuggees on
+//! all instruments.
+//!
+//! The console is meant for people. A front end that parses the debugger events attaches
+//! to the [`TcpFrontEnd`] of one instrument instead, which gets the output of only that
+//! instrument, unchanged, and sends commands to only that instrument.
+use std::{
+    io::{self, Write},
+    sync::{
+        mpsc::{channel, Receiver},
+        Arc, Mutex, PoisonError,
+    },
+    thread,
+};
+
+use colored::Colorize;
+
+use super::{
+    frontend::{CommandSource, TcpFrontEnd, TcpFrontEndOutput},
+    Debugger,
+};
+use crate::error::{DebugError, Result};
+
+/// Connects to an instrument and sets up its debugger. It runs on the thread of the
+/// instrument, so the instrument doesn't need to be [`Send`].
+pub type Connect = Box<dyn FnOnce() -> Result<Debugger> + Send>;
+
+/// The console command that pauses the debuggees on all instruments.
+pub const PAUSE_ALL: &str = ".debug pauseAll";
+/// The console command that continues the debuggees on all instruments.
+pub const CONTINUE_ALL: &str = ".debug continueAll";
+
+/// The instruments that are debugged together.
+#[derive(Default)]
+pub struct Rack {
+    members: Vec<(String, Connect, Option<TcpFrontEnd>)>,
+}
+
+impl Rack {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Debug the instrument that `connect` connects to. `name` addresses it on the
+    /// console and is shown in front of its output. The instrument can also be debugged
+    /// from `front_end`.
+    pub fn add(
+        &mut self,
+        name: impl Into<String>,
+        connect: Connect,
+        front_end: Option<TcpFrontEnd>,
+    ) {
+        self.members.push((name.into(), connect, front_end));
+    }
+
+    /// Connect to all instruments and run their debug sessions until every one of them
+    /// has ended. Commands are read from `commands` and all output is written to
+    /// `output`.
+    ///
+    /// # Errors
+    /// Returns [`DebugError::Other`] if any of the sessions failed. The errors of the
+    /// sessions are written to `output` with the name of their instrument.
+    pub fn start(
+        self,
+        mut commands: Box<dyn CommandSource>,
+        output: Box<dyn Write + Send>,
+    ) -> Result<()> {
+        let console = Arc::new(Mutex::new(Console {
+            output,
+            open_line: None,
+        }));
+        let total = self.members.len();
+        let names: Vec<String> = self.members.iter().map(|(name, ..)| name.clone()).collect();
+
+        let mut inputs = Vec::new();
+        let mut sessions = Vec::new();
+        for (id, (name, connect, front_end)) in self.members.into_iter().enumerate() {
+            let (input, commands) = channel();
+            let mut output = MemberOutput {
+                id,
+                prefix: format!("[{name}] ").cyan().to_string(),
+                console: Arc::clone(&console),
+                front_end: front_end.as_ref().map(TcpFrontEnd::output),
+            };
+            if let Some(mut front_end) = front_end {
+                let input = input.clone();
+                // Like the console, this is left to end with the process.
+                thread::Builder::new()
+                    .name(format!("rack_{name}_front_end"))
+                    .spawn(move || loop {
+                        let mut line = String::new();
+                        if front_end.read_line(&mut line).unwrap_or(0) == 0
+                            || input.send(line).is_err()
+                        {
+                            return;
+                        }
+                    })?;
+            }
+            inputs.push((name.clone(), input));
+            let (ready, connected) = channel();
+            sessions.push(thread::Builder::new().name(format!("rack_{name}")).spawn(
+                move || {
+                    let debugger = connect();
+                    let _ = ready.send(());
+                    let result = debugger.and_then(|mut debugger| {
+                        // `.debug pauseAll` pauses every member.
+                        debugger.set_pausable(true);
+                        debugger.set_front_end(
+                            Box::new(ChannelSource(commands)),
+                            Box::new(output.clone()),
+                        );
+                        debugger.start()
+                    });
+                    if let Err(e) = &result {
+                        let _ = writeln!(output, "{}", format!("Error: {e}").red());
+                    }
+                    result
+                },
+            )?);
+            // Credentials may be prompted for while connecting, so the instruments are
+            // connected one after the other before the console starts.
+            let _ = connected.recv();
+        }
+
+        console.lock().unwrap_or_else(PoisonError::into_inner).message(&format!(
+            "Debugging on {}. Start a command with @<name> to send it to one instrument, `{PAUSE_ALL}` and `{CONTINUE_ALL}` pause and continue all of them.",
+            names.join(", ")
+        ));
+        let console_in = Arc::clone(&console);
+        // Like the user input of a single session, the console is left to end with the
+        // process once all sessions are over.
+        thread::Builder::new()
+            .name("rack_console".to_string())
+            .spawn(move || -> io::Result<()> {
+                loop {
+                    let mut line = String::new();
+                    if commands.read_line(&mut line)? == 0 {
+                        return Ok(());
+                    }
+                    let (target, command) = route(&line);
+                    let mut sent = false;
+                    for (name, input) in &inputs {
+                        if target.is_none_or(|t| t == name) {
+                            sent |= input.send(command.clone()).is_ok();
+                        }
+                    }
+                    if let (Some(target), false) = (target, sent) {
+                        console_in
+                            .lock()
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .message(&format!(
+                                "No debug session on '{target}', use one of {}",
+                                names.join(", ")
+                            ));
+                    }
+                }
+            })?;
+
+        let failed = sessions
+            .into_iter()
+            .map(thread::JoinHandle::join)
+            .filter(|result| !matches!(result, Ok(Ok(()))))
+            .count();
+        if failed > 0 {
+            return Err(DebugError::Other(format!(
+                "{failed} of {total} debug sessions failed"
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Split a console line into the instrument it is addressed to, `None` for all of
+/// them, and the command for the instrument.
+#[must_use]
+pub fn route(line: &str) -> (Option<&str>, String) {
+    let line = line.trim();
+    let (target, command) = match line.strip_prefix('@') {
+        Some(addressed) => {
+            let (name, command) = addressed
+                .split_once(char::is_whitespace)
+                .unwrap_or((addressed, ""));
+            (Some(name), command.trim())
+        }
+        None => (None, line),
+    };
+    let command = match command {
+        PAUSE_ALL => ".debug pause",
+        CONTINUE_ALL => ".debug run",
+        command => command,
+    };
+    (target, format!("{command}\n"))
+}
+
+/// The shared console output.
+struct Console {
+    output: Box<dyn Write + Send>,
+    /// The member whose last line isn't finished yet
+    open_line: Option<usize>,
+}
+
+impl Console {
+    /// Write a message of the rack itself on its own line.
+    fn message(&mut self, message: &str) {
+        let newline = if self.open_line.take().is_some() {
+            "\n"
+        } else {
+            ""
+        };
+        let _ = writeln!(self.output, "{newline}{}", message.yellow());
+        let _ = self.output.flush();
+    }
+}
+
+/// Writes the output of one member to the console, with its name in front of every
+/// line, and unchanged to the front end of the member.
+#[derive(Clone)]
+struct MemberOutput {
+    id: usize,
+    prefix: String,
+    console: Arc<Mutex<Console>>,
+    front_end: Option<TcpFrontEndOutput>,
+}
+
+impl Write for MemberOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(front_end) = &mut self.front_end {
+            front_end.write_all(buf)?;
+        }
+        let mut console = self.console.lock().unwrap_or_else(PoisonError::into_inner);
+        for line in buf.split_inclusive(|&b| b == b'\n') {
+            if console.open_line != Some(self.id) {
+                // Finish the line of another member before starting one of this member.
+                if console.open_line.is_some() {
+                    console.output.write_all(b"\n")?;
+                }
+                console.output.write_all(self.prefix.as_bytes())?;
+            }
+            console.output.write_all(line)?;
+            console.open_line = (!line.ends_with(b"\n")).then_some(self.id);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(front_end) = &mut self.front_end {
+            front_end.flush()?;
+        }
+        self.console
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .output
+            .flush()
+    }
+}
+
+/// Feeds the commands routed to one member to its debugger.
+struct ChannelSource(Receiver<String>);
+
+impl CommandSource for ChannelSource {
+    fn read_line(&mut self, buf: &mut String) -> io::Result<usize> {
+        let line = self.0.recv().map_err(|_| {
+            io::Error::new(io::ErrorKind::BrokenPipe, "the rack console was closed")
+        })?;
+        buf.push_str(&line);
+        Ok(line.len())
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use std::sync::{Arc, Mutex};
+
+    use super::{route, Console, MemberOutput};
+    use std::io::Write;
+
+    #[test]
+    fn console_lines_are_routed() {
+        assert_eq!(route(".debug run\n"), (None, ".debug run\n".to_string()));
+        assert_eq!(
+            route("@smu2  .debug stepOver\n"),
+            (Some("smu2"), ".debug stepOver\n".to_string())
+        );
+        assert_eq!(
+            route(".debug pauseAll"),
+            (None, ".debug pause\n".to_string())
+        );
+        assert_eq!(route("@smu1"), (Some("smu1"), "\n".to_string()));
+    }
+
+    #[test]
+    fn member_output_is_prefixed() {
+        #[derive(Clone, Default)]
+        struct Shared(Arc<Mutex<Vec<u8>>>);
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        colored::control::set_override(false);
+        let shared = Shared::default();
+        let console = Arc::new(Mutex::new(Console {
+            output: Box::new(shared.clone()),
+            open_line: None,
+        }));
+        let member = |id, name: &str| MemberOutput {
+            id,
+            prefix: format!("[{name}] "),
+            console: Arc::clone(&console),
+            front_end: None,
+        };
+        let (mut a, mut b) = (member(0, "a"), member(1, "b"));
+
+        write!(a, "one\nTSP> ").unwrap();
+        writeln!(b, "two").unwrap();
+        writeln!(a, "three").unwrap();
+
+        assert_eq!(
+            String::from_utf8(shared.0.lock().unwrap().clone()).unwrap(),
+            "[a] one\n[a] TSP> \n[b] two\n[a] three\n"
+        );
+    }
+}
@@ -0,0 +1,151 @@
+//! Re-establish the connection to the instrument after it was lost mid-session.
+use std::{io::ErrorKind, thread, time::Duration};
+
+use kic_lib::{
+    instrument::{authenticate::Authentication, Instrument, State},
+    model::connect_to,
+    ConnectionInfo,
+};
+
+use crate::error::{DebugError, Result};
+
+/// Marks the start of the status reply of the on-instrument debugger.
+pub const STATUS_BEGIN: &str = "<debugger-status >";
+/// Marks the end of the status reply of the on-instrument debugger.
+pub const STATUS_END: &str = "</debugger-status>";
+
+/// The TSP command that asks the on-instrument debugger for the state of the debuggee.
+/// It is answered at the prompt, while the debuggee is paused and while it runs in a
+/// session that can be paused. A debuggee running in any other session only answers
+/// once it ended.
+pub const STATUS_QUERY: &str = "kiStatus()\n";
+
+/// The state of the debuggee reported by the on-instrument debugger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebuggeeStatus {
+    Running,
+    Paused,
+    Ended,
+}
+
+impl DebuggeeStatus {
+    /// Find and parse the status reply in the given instrument output.
+    ///
+    /// Returns `None` if the output doesn't contain a complete status reply.
+    #[must_use]
+    pub fn parse(output: &str) -> Option<Self> {
+        let end = output.rfind(STATUS_END)?;
+        let begin = output[..end].rfind(STATUS_BEGIN)?;
+        match output[begin.checked_add(STATUS_BEGIN.len())?..end].trim() {
+            "running" => Some(Self::Running),
+            "paused" => Some(Self::Paused),
+            "ended" => Some(Self::Ended),
+            _ => None,
+        }
+    }
+}
+
+/// How to reconnect to the instrument the session was started on.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    conn: ConnectionInfo,
+    auth: Authentication,
+    attempts: usize,
+    delay: Duration,
+}
+
+impl ReconnectPolicy {
+    /// Reconnect with the same connection information and authentication that were
+    /// used for the original connection.
+    #[must_use]
+    pub const fn new(conn: ConnectionInfo, auth: Authentication) -> Self {
+        Self {
+            conn,
+            auth,
+            attempts: 5,
+            delay: Duration::from_secs(2),
+        }
+    }
+
+    /// Whether the given error means that the connection to the instrument was lost.
+    #[must_use]
+    pub fn is_connection_lost(error: &std::io::Error) -> bool {
+        matches!(
+            error.kind(),
+            ErrorKind::ConnectionReset
+                | ErrorKind::NotConnected
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::BrokenPipe
+        )
+    }
+
+    /// Connect to the instrument again, logging in if needed.
+    ///
+    /// # Errors
+    /// Returns [`DebugError::ConnectionLost`] if no connection could be made within the
+    /// allowed number of attempts.
+    pub fn reconnect(&self) -> Result<Box<dyn Instrument>> {
+        let mut last_error = None;
+        for attempt in 1..=self.attempts {
+            if attempt > 1 {
+                thread::sleep(self.delay);
+            }
+            match self.connect() {
+                Ok(instrument) => return Ok(instrument),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(DebugError::ConnectionLost {
+            attempts: self.attempts,
+            source: last_error.unwrap_or_else(|| {
+                kic_lib::InstrumentError::Other("no reconnect attempts were made".to_string())
+            }),
+        })
+    }
+
+    fn connect(&self) -> std::result::Result<Box<dyn Instrument>, kic_lib::InstrumentError> {
+        let mut instrument = connect_to(&self.conn, self.auth.clone())?;
+        if instrument.check_login()? == State::Needed {
+            instrument.login()?;
+        }
+        Ok(instrument)
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use std::io::{Error, ErrorKind};
+
+    use super::{DebuggeeStatus, ReconnectPolicy, STATUS_BEGIN, STATUS_END};
+
+    #[test]
+    fn detects_connection_loss() {
+        assert!(ReconnectPolicy::is_connection_lost(&Error::from(
+            ErrorKind::ConnectionReset
+        )));
+        assert!(ReconnectPolicy::is_connection_lost(&Error::from(
+            ErrorKind::NotConnected
+        )));
+        assert!(!ReconnectPolicy::is_connection_lost(&Error::from(
+            ErrorKind::WouldBlock
+        )));
+    }
+
+    #[test]
+    fn status_replies_are_parsed() {
+        let frame = |state: &str| {
+            format!("<tspdbg-AA4E9540-A46C-4671-81D7-4FE69A9B6DC4>{STATUS_BEGIN}{state}{STATUS_END}</tspdbg-AA4E9540-A46C-4671-81D7-4FE69A9B6DC4>\n")
+        };
+
+        assert_eq!(
+            DebuggeeStatus::parse(&frame("paused")),
+            Some(DebuggeeStatus::Paused)
+        );
+        assert_eq!(
+            DebuggeeStatus::parse(&format!("x = 1\n{}", frame("ended"))),
+            Some(DebuggeeStatus::Ended)
+        );
+        assert_eq!(DebuggeeStatus::parse(&frame("lost")), None);
+        assert_eq!(DebuggeeStatus::parse(STATUS_BEGIN), None);
+    }
+}
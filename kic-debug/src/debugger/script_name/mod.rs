@@ -0,0 +1,65 @@
+//! Names for the debuggee scripts on the instrument.
+//!
+//! Script names on the instrument are limited in length, so the file stem is truncated
+//! and a short hash of the full source path is appended to keep names unique.
+use super::cache::content_hash;
+
+/// The prefix of every debuggee script name.
+pub const SCRIPT_NAME_PREFIX: &str = "kic_";
+/// The longest script name the instrument accepts.
+pub const MAX_SCRIPT_NAME_LEN: usize = 31;
+
+const HASH_LEN: usize = 8;
+
+/// The on-instrument name for a debuggee script.
+///
+/// * `stem` - The file stem of the script, shown in the name for readability
+/// * `key` - What makes the script unique, usually its full path
+#[must_use]
+pub fn script_name(stem: &str, key: &str) -> String {
+    let stem_len = MAX_SCRIPT_NAME_LEN - SCRIPT_NAME_PREFIX.len() - HASH_LEN - 1;
+    let stem: String = stem
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(stem_len)
+        .collect();
+    let hash = &content_hash(key)[..HASH_LEN];
+    format!("{SCRIPT_NAME_PREFIX}{stem}_{hash}")
+}
+
+/// The Lua pattern that matches the names made by [`script_name`]. A name also has to
+/// be at most [`MAX_SCRIPT_NAME_LEN`] long to be one of them.
+#[must_use]
+pub fn lua_pattern() -> String {
+    format!("^{SCRIPT_NAME_PREFIX}[%w_]*_{}$", "%x".repeat(HASH_LEN))
+}
+
+#[cfg(test)]
+mod unit {
+    use super::{script_name, MAX_SCRIPT_NAME_LEN};
+
+    #[test]
+    fn long_names_stay_unique() {
+        let a = script_name("measure_all_channels_sweep_a", "/scripts/a.tsp");
+        let b = script_name("measure_all_channels_sweep_b", "/scripts/b.tsp");
+
+        assert_ne!(a, b);
+        assert!(a.len() <= MAX_SCRIPT_NAME_LEN);
+        assert!(b.len() <= MAX_SCRIPT_NAME_LEN);
+    }
+
+    #[test]
+    fn names_are_lua_identifiers() {
+        let name = script_name("mesure_ü", "/scripts/mesure ü.tsp");
+
+        assert!(name.starts_with("kic_mesure__"));
+        assert!(name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'));
+        assert_eq!(name, script_name("mesure_ü", "/scripts/mesure ü.tsp"));
+    }
+}
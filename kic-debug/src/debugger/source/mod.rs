@@ -0,0 +1,80 @@
+//! Debuggee source that is given inline or piped on stdin instead of as a file on disk,
+//! e.g. generated TSP or an unsaved editor buffer.
+use std::io;
+
+use super::frontend::CommandSource;
+
+/// The `FileName` that makes the debugger read the source from the command input. The
+/// lines after the `.debug` command are the source, up to [`STDIN_SOURCE_END`].
+pub const STDIN_FILE_NAME: &str = "-";
+/// The line that ends source piped on the command input.
+pub const STDIN_SOURCE_END: &str = ".debug endSource";
+/// The virtual file name of source piped on the command input.
+pub const STDIN_VIRTUAL_FILE_NAME: &str = "stdin.tsp";
+/// The virtual file name of inline source that was given without a `FileName`.
+pub const INLINE_VIRTUAL_FILE_NAME: &str = "inline.tsp";
+
+/// The file name the debugger reports for inline source, so that a front end can map
+/// lines to it. The same name is used every time, so it stays stable across restarts.
+#[must_use]
+pub fn virtual_file_name(file_name: &str) -> String {
+    let file_name = file_name.trim();
+    if file_name.is_empty() || file_name == STDIN_FILE_NAME {
+        INLINE_VIRTUAL_FILE_NAME.to_string()
+    } else {
+        file_name.to_string()
+    }
+}
+
+/// Read source lines from `commands` up to [`STDIN_SOURCE_END`] or the end of the input.
+///
+/// # Errors
+/// Returns an IO error if the input could not be read.
+pub fn read_stdin_source(commands: &mut dyn CommandSource) -> io::Result<String> {
+    let mut source = String::new();
+    loop {
+        let mut line = String::new();
+        if commands.read_line(&mut line)? == 0 || line.trim_end() == STDIN_SOURCE_END {
+            break;
+        }
+        source.push_str(&line);
+    }
+    Ok(source)
+}
+
+#[cfg(test)]
+mod unit {
+    use std::io::{self, BufRead, Cursor};
+
+    use super::{read_stdin_source, virtual_file_name, CommandSource};
+
+    struct Lines(Cursor<&'static str>);
+
+    impl CommandSource for Lines {
+        fn read_line(&mut self, buf: &mut String) -> io::Result<usize> {
+            self.0.read_line(buf)
+        }
+    }
+
+    #[test]
+    fn read_until_end_marker() {
+        let mut commands = Lines(Cursor::new(
+            "x = 1\nprint(x)\n.debug endSource\n.debug run\n",
+        ));
+
+        assert_eq!(
+            read_stdin_source(&mut commands).unwrap(),
+            "x = 1\nprint(x)\n"
+        );
+
+        let mut next = String::new();
+        commands.read_line(&mut next).unwrap();
+        assert_eq!(next, ".debug run\n");
+    }
+
+    #[test]
+    fn virtual_names() {
+        assert_eq!(virtual_file_name(""), "inline.tsp");
+        assert_eq!(virtual_file_name("Untitled-1"), "Untitled-1");
+    }
+}
@@ -1,6 +1,9 @@
 ///The variable struct to hold the deserialized
 /// json data when .debug setVariable is invoked
 use serde::{Deserialize, Serialize};
+
+use crate::error::{DebugError, Result};
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct VariableInfo {
     #[serde(rename = "StackLevel")]
@@ -9,6 +12,273 @@ pub struct VariableInfo {
     pub argument_list: Vec<String>,
     #[serde(rename = "Value")]
     pub value: String,
+    /// How `value` should be interpreted, an expression if not given
+    #[serde(rename = "Type", default)]
+    pub value_type: ValueType,
     #[serde(rename = "Scope")]
-    pub scope_type: String,
+    pub scope_type: Scope,
+}
+
+impl VariableInfo {
+    /// The typed value to assign.
+    ///
+    /// # Errors
+    /// Returns a [`DebugError::CommandError`] if `value` isn't valid for `value_type`.
+    pub fn value(&self) -> Result<Value> {
+        let invalid = || DebugError::CommandError {
+            details: format!(
+                "'{}' is not a valid {:?} value",
+                self.value, self.value_type
+            ),
+        };
+        Ok(match self.value_type {
+            ValueType::Number => {
+                let number: f64 = self.value.trim().parse().map_err(|_| invalid())?;
+                if !number.is_finite() {
+                    return Err(invalid());
+                }
+                Value::Number(number)
+            }
+            ValueType::String => Value::String(self.value.clone()),
+            ValueType::Boolean => match self.value.trim() {
+                "true" => Value::Boolean(true),
+                "false" => Value::Boolean(false),
+                _ => return Err(invalid()),
+            },
+            ValueType::Nil => match self.value.trim() {
+                "" | "nil" => Value::Nil,
+                _ => return Err(invalid()),
+            },
+            ValueType::Expression => Value::Expression(self.value.clone()),
+        })
+    }
+
+    /// The TSP arguments naming the variable: its name followed by the keys of the
+    /// nested fields, e.g. `"tab","x",1`. Front ends send them ready to be passed on,
+    /// names as quoted string literals and array indices as integers.
+    ///
+    /// # Errors
+    /// Returns a [`DebugError::CommandError`] if an element is neither.
+    pub fn arguments(&self) -> Result<String> {
+        let elements = self
+            .argument_list
+            .iter()
+            .map(|element| {
+                let element = element.trim();
+                if is_lua_string_literal(element) || element.parse::<i64>().is_ok() {
+                    Ok(element)
+                } else {
+                    Err(DebugError::CommandError {
+                        details: format!("'{element}' is not a quoted name or an index"),
+                    })
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if elements.is_empty() {
+            return Err(DebugError::CommandError {
+                details: "the ArgumentList is empty".to_string(),
+            });
+        }
+        Ok(elements.join(","))
+    }
+}
+
+/// Where the variable to set lives.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Locals,
+    Upvalues,
+    Globals,
+}
+
+impl Scope {
+    /// The kiDebugger function that sets a variable in this scope.
+    #[must_use]
+    pub const fn setter(self) -> &'static str {
+        match self {
+            Self::Locals => "kiSetLocalVariable",
+            Self::Upvalues => "kiSetUpVariable",
+            Self::Globals => "kiSetGlobalVariable",
+        }
+    }
+
+    /// The element kiDebugger replies with after setting a variable in this scope.
+    #[must_use]
+    pub const fn reply_element(self) -> &'static str {
+        match self {
+            Self::Locals => "setLocalVariable",
+            Self::Upvalues => "setUpVariable",
+            Self::Globals => "setGlobalVariable",
+        }
+    }
+}
+
+/// The type of a value given for `.debug setVariable`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueType {
+    Number,
+    String,
+    Boolean,
+    Nil,
+    /// A TSP expression that is evaluated on the instrument
+    #[default]
+    Expression,
+}
+
+/// A value to assign to a variable.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f64),
+    String(String),
+    Boolean(bool),
+    Nil,
+    /// A TSP expression that is evaluated on the instrument
+    Expression(String),
+}
+
+impl Value {
+    /// The TSP expression that evaluates to this value.
+    #[must_use]
+    pub fn to_expression(&self) -> String {
+        match self {
+            Self::Number(n) => n.to_string(),
+            Self::String(s) => lua_string_literal(s),
+            Self::Boolean(b) => b.to_string(),
+            Self::Nil => "nil".to_string(),
+            Self::Expression(e) => e.clone(),
+        }
+    }
+}
+
+/// Quote `s` as a TSP string literal, escaping anything that would end or corrupt it.
+#[must_use]
+pub fn lua_string_literal(s: &str) -> String {
+    let mut literal = String::with_capacity(s.len() + 2);
+    literal.push('"');
+    for c in s.chars() {
+        match c {
+            '\\' => literal.push_str("\\\\"),
+            '"' => literal.push_str("\\\""),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            '\t' => literal.push_str("\\t"),
+            c if c.is_ascii_control() => literal.push_str(&format!("\\{:03}", c as u8)),
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// Whether `element` is a single quoted TSP string literal, so that it can be passed on
+/// to the instrument as it is.
+fn is_lua_string_literal(element: &str) -> bool {
+    let mut chars = element.chars();
+    let Some(quote @ ('"' | '\'')) = chars.next() else {
+        return false;
+    };
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if chars.next().is_none() {
+                    return false;
+                }
+            }
+            '\n' | '\r' => return false,
+            c if c == quote => return chars.next().is_none(),
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Find the reply to setting a variable in the given debugger output.
+///
+/// # Returns
+/// `Some(true)` if the variable was set, `Some(false)` if it wasn't and `None` if the
+/// output doesn't contain a reply.
+#[must_use]
+pub fn parse_reply(output: &str, element: &str) -> Option<bool> {
+    if output.contains(&format!("<{element} />")) {
+        Some(false)
+    } else if output.contains(&format!("</{element}>")) {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use super::{lua_string_literal, parse_reply, Scope, Value, ValueType, VariableInfo};
+
+    #[test]
+    fn typed_values() {
+        let info: VariableInfo = serde_json::from_str(
+            r#"{"StackLevel":0,"ArgumentList":["x"],"Value":"a\\b\n\"c\"","Type":"string","Scope":"locals"}"#,
+        )
+        .unwrap();
+
+        assert_eq!(info.scope_type, Scope::Locals);
+        assert_eq!(info.value().unwrap().to_expression(), r#""a\\b\n\"c\"""#);
+        assert_eq!(
+            lua_string_literal(&info.value().unwrap().to_expression()),
+            r#""\"a\\\\b\\n\\\"c\\\"\"""#
+        );
+
+        let number = VariableInfo {
+            value: "1.5".to_string(),
+            value_type: ValueType::Number,
+            ..info.clone()
+        };
+        assert_eq!(number.value().unwrap(), Value::Number(1.5));
+        let not_a_number = VariableInfo {
+            value: "x + 1".to_string(),
+            ..number
+        };
+        assert!(not_a_number.value().is_err());
+    }
+
+    #[test]
+    fn arguments_are_passed_on() {
+        let info = |list: &[&str]| VariableInfo {
+            stack_level: 0,
+            argument_list: list.iter().map(ToString::to_string).collect(),
+            value: "7".to_string(),
+            value_type: ValueType::Number,
+            scope_type: Scope::Locals,
+        };
+
+        assert_eq!(
+            info(&[r#""t""#, r#"'a\'b'"#, "2"]).arguments().unwrap(),
+            r#""t",'a\'b',2"#
+        );
+        assert!(info(&["t"]).arguments().is_err());
+        assert!(info(&[r#""x") os.exit() --""#]).arguments().is_err());
+        assert!(info(&[r#""x\""#]).arguments().is_err());
+        assert!(info(&[r#""t""#, "1.5"]).arguments().is_err());
+        assert!(info(&[]).arguments().is_err());
+    }
+
+    #[test]
+    fn unknown_scope_is_rejected() {
+        let info: Result<VariableInfo, _> = serde_json::from_str(
+            r#"{"StackLevel":0,"ArgumentList":["x"],"Value":"7","Scope":"local"}"#,
+        );
+
+        assert!(info.is_err());
+    }
+
+    #[test]
+    fn parse_replies() {
+        let tag = "tspdbg-AA4E9540-A46C-4671-81D7-4FE69A9B6DC4";
+        let set = format!("<{tag}><setLocalVariable >x</setLocalVariable></{tag}>");
+        let not_set = format!("<{tag}><setUpVariable /></{tag}>");
+
+        assert_eq!(parse_reply(&set, "setLocalVariable"), Some(true));
+        assert_eq!(parse_reply(&not_set, "setUpVariable"), Some(false));
+        assert_eq!(parse_reply("", "setUpVariable"), None);
+    }
 }
@@ -1,10 +1,150 @@
 ///The Watchpoint struct to hold the deserialized
 /// json data when .debug setWatchpoint is invoked
 use serde::{Deserialize, Serialize};
+
+use crate::error::{DebugError, Result};
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct WatchpointInfo {
     #[serde(rename = "Enable")]
     pub enable: bool,
     #[serde(rename = "Expression")]
     pub expression: String,
+    /// How the expression is evaluated, detected from the expression if not given
+    #[serde(rename = "Kind", default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<WatchKind>,
+}
+
+/// How a watch entry is evaluated at each stop.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchKind {
+    /// A Lua expression in the scope of the stop
+    Expression,
+    /// A SCPI query such as `*STB?`
+    Scpi,
+    /// An instrument attribute such as `smua.source.output`, read without calling
+    /// any function
+    Attribute,
+}
+
+impl WatchKind {
+    /// The name of the kind on the on-instrument debugger, which is also the type
+    /// reported for SCPI and attribute watch entries.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Expression => "expression",
+            Self::Scpi => "scpi",
+            Self::Attribute => "attribute",
+        }
+    }
+}
+
+/// The SCPI queries that can be watched. They only read the status registers or the
+/// identification of the instrument.
+pub const WATCHABLE_SCPI_QUERIES: [&str; 4] = ["*STB?", "*ESE?", "*SRE?", "*IDN?"];
+
+impl WatchpointInfo {
+    /// The given kind, or [`WatchKind::Scpi`] for expressions starting with `*` like
+    /// `tspdbg:dostring` does.
+    ///
+    /// Returns `None` if the on-instrument debugger decides: dotted names whose root is
+    /// an instrument object are watched as attributes, anything else as expressions.
+    #[must_use]
+    pub fn kind(&self) -> Option<WatchKind> {
+        self.kind.or_else(|| {
+            self.expression
+                .trim_start()
+                .starts_with('*')
+                .then_some(WatchKind::Scpi)
+        })
+    }
+
+    /// Make sure the watch entry can be evaluated at every stop without side effects.
+    /// The expression is validated as it is sent, without surrounding whitespace.
+    ///
+    /// # Errors
+    /// Returns a [`DebugError::CommandError`] if a SCPI entry isn't one of
+    /// [`WATCHABLE_SCPI_QUERIES`] or an attribute entry isn't a dotted name like
+    /// `node[2].smua.source.output`.
+    pub fn validate(&self) -> Result<()> {
+        let expression = self.expression.trim();
+        match self.kind() {
+            Some(WatchKind::Scpi)
+                if !WATCHABLE_SCPI_QUERIES
+                    .iter()
+                    .any(|q| q.eq_ignore_ascii_case(expression)) =>
+            {
+                Err(DebugError::CommandError {
+                    details: format!(
+                        "'{expression}' can't be watched, only {} can",
+                        WATCHABLE_SCPI_QUERIES.join(", ")
+                    ),
+                })
+            }
+            Some(WatchKind::Attribute) if !is_attribute_path(expression) => {
+                Err(DebugError::CommandError {
+                    details: format!("'{expression}' is not an instrument attribute"),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Whether `path` is a name followed by fields and numeric indexes, e.g.
+/// `node[2].smua.source.output`.
+fn is_attribute_path(path: &str) -> bool {
+    let is_name = |name: &str| {
+        name.chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+    path.contains(['.', '['])
+        && path.split('.').all(|part| {
+            let mut pieces = part.split('[');
+            is_name(pieces.next().unwrap_or_default())
+                && pieces.all(|index| {
+                    index
+                        .strip_suffix(']')
+                        .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+                })
+        })
+}
+
+#[cfg(test)]
+mod unit {
+    use super::{WatchKind, WatchpointInfo};
+
+    fn watch(expression: &str, kind: Option<WatchKind>) -> WatchpointInfo {
+        WatchpointInfo {
+            enable: true,
+            expression: expression.to_string(),
+            kind,
+        }
+    }
+
+    #[test]
+    fn instrument_watch_entries_are_validated() {
+        assert_eq!(watch("*STB?", None).kind(), Some(WatchKind::Scpi));
+        assert_eq!(watch("x + 1", None).kind(), None);
+        assert!(watch("*STB?", None).validate().is_ok());
+        assert!(watch(" *idn? ", None).validate().is_ok());
+        assert!(watch("*RST", None).validate().is_err());
+        assert!(watch("*OPC?", None).validate().is_err());
+        assert!(watch("*STB?; *RST", None).validate().is_err());
+        assert!(
+            watch("node[2].smua.source.output", Some(WatchKind::Attribute))
+                .validate()
+                .is_ok()
+        );
+        assert!(watch("smua.reset()", Some(WatchKind::Attribute))
+            .validate()
+            .is_err());
+        assert!(watch("smua", Some(WatchKind::Attribute))
+            .validate()
+            .is_err());
+    }
 }
@@ -3,4 +3,6 @@ const VERSION: &str = env!("CARGO_PKG_VERSION");
 pub mod command;
 pub mod debugger;
 pub mod error;
+pub mod minify;
 pub mod resources;
+pub mod test_runner;
@@ -0,0 +1,158 @@
+//! A minifier for TSP (Lua 5.0) source that strips comments and redundant whitespace
+//! while keeping every statement on its original line, so that breakpoints and error
+//! messages still refer to the right line numbers.
+
+/// Minify the given TSP source.
+///
+/// Comments are removed (multi-line comments are replaced by the line breaks they
+/// contained), indentation and trailing whitespace are removed, and whitespace between
+/// tokens is only kept where removing it would join two tokens. String literals,
+/// including long strings, are kept as-is.
+#[must_use]
+pub fn minify(source: &str) -> String {
+    let chars: Vec<char> = source.chars().collect();
+    let mut out = String::with_capacity(source.len());
+    let mut pending_space = false;
+    let mut i = 0;
+
+    while let Some(&c) = chars.get(i) {
+        let next = chars.get(i + 1).copied();
+        match c {
+            '\n' => {
+                pending_space = false;
+                out.push('\n');
+                i += 1;
+            }
+            '-' if next == Some('-') => {
+                if chars.get(i + 2) == Some(&'[') && chars.get(i + 3) == Some(&'[') {
+                    let end = long_bracket_end(&chars, i + 2);
+                    out.extend(chars[i..end].iter().filter(|&&c| c == '\n'));
+                    i = end;
+                } else {
+                    while chars.get(i).is_some_and(|&c| c != '\n') {
+                        i += 1;
+                    }
+                }
+                pending_space = true;
+            }
+            c if c.is_whitespace() => {
+                pending_space = true;
+                i += 1;
+            }
+            '"' | '\'' => {
+                push_separated(&mut out, &mut pending_space, c);
+                let end = short_string_end(&chars, i);
+                out.extend(&chars[i + 1..end]);
+                i = end;
+            }
+            '[' if next == Some('[') => {
+                push_separated(&mut out, &mut pending_space, c);
+                let end = long_bracket_end(&chars, i);
+                out.extend(&chars[i + 1..end]);
+                i = end;
+            }
+            c => {
+                push_separated(&mut out, &mut pending_space, c);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Push `c`, preceded by a single space if there was whitespace before it that can't
+/// be removed without joining two tokens.
+fn push_separated(out: &mut String, pending_space: &mut bool, c: char) {
+    if std::mem::take(pending_space) {
+        if let Some(last) = out.chars().last() {
+            if last != '\n' && needs_space(last, c) {
+                out.push(' ');
+            }
+        }
+    }
+    out.push(c);
+}
+
+/// Whether the characters `a` and `b` would form a different token if they weren't
+/// separated by whitespace.
+fn needs_space(a: char, b: char) -> bool {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == '.' || !c.is_ascii();
+    (is_word(a) && is_word(b))
+        || matches!(
+            (a, b),
+            ('=' | '~' | '<' | '>', '=') | ('-', '-') | ('[', '[')
+        )
+}
+
+/// The index one past the closing quote of the short string that starts at `start`.
+/// An unterminated string ends at the end of its line.
+fn short_string_end(chars: &[char], start: usize) -> usize {
+    let quote = chars[start];
+    let mut i = start + 1;
+    while let Some(&c) = chars.get(i) {
+        match c {
+            '\\' => i += 2,
+            '\n' => return i,
+            c if c == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+    chars.len()
+}
+
+/// The index one past the closing `]]` of the long bracket that starts at `start`.
+/// Long brackets nest in Lua 5.0.
+fn long_bracket_end(chars: &[char], start: usize) -> usize {
+    let mut depth = 0usize;
+    let mut i = start;
+    while i + 1 < chars.len() {
+        match (chars[i], chars[i + 1]) {
+            ('[', '[') => {
+                depth += 1;
+                i += 2;
+            }
+            (']', ']') => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    chars.len()
+}
+
+#[cfg(test)]
+mod unit {
+    use super::minify;
+    use crate::resources::{KIDEBUGGER_TSP, TSPDBG_TSP};
+
+    #[test]
+    fn strips_comments_and_whitespace() {
+        let source = "-- header\nlocal  x = 1 -- one\n\n  --[[ a\n  b ]] if x  ==  1 then\n\tprint( \"a  -- b\" , x - -x )\nend\n";
+        let expected = "\nlocal x=1\n\n\nif x==1 then\nprint(\"a  -- b\",x- -x)\nend\n";
+
+        assert_eq!(minify(source), expected);
+    }
+
+    #[test]
+    fn keeps_long_strings() {
+        let source = "local op =\n    [[\n  MOVE [[x]]   LOADK\n]]  .. \"\"\n";
+        let expected = "local op=\n[[\n  MOVE [[x]]   LOADK\n]]..\"\"\n";
+
+        assert_eq!(minify(source), expected);
+    }
+
+    #[test]
+    fn embedded_scripts_keep_line_numbers() {
+        for resource in [KIDEBUGGER_TSP, TSPDBG_TSP] {
+            let source = resource.decrypt().unwrap().to_string();
+            let minified = minify(&source);
+
+            assert_eq!(minified.lines().count(), source.lines().count());
+            assert!(minified.len() < source.len());
+        }
+    }
+}
@@ -1,14 +1,20 @@
 use std::fmt::Display;
 
+pub mod cipher;
+pub use cipher::DecryptError;
+
 use crate::{error::Result, VERSION};
 const VERSION_REPLACE: &str = "!<!<VERSION>!>!";
 
+// Generated by the build script along with the encrypted resources.
+include!(concat!(env!("OUT_DIR"), "/resource_key.rs"));
+
 pub const KIDEBUGGER_TSP: EncryptedResource = EncryptedResource {
-    source: include_bytes!("./kiDebugger.tsp"),
+    source: include_bytes!(concat!(env!("OUT_DIR"), "/kiDebugger.tsp.enc")),
 };
 
 pub const TSPDBG_TSP: EncryptedResource = EncryptedResource {
-    source: include_bytes!("./tspdbg.tsp"),
+    source: include_bytes!(concat!(env!("OUT_DIR"), "/tspdbg.tsp.enc")),
 };
 
 /// An encrypted resource that needs to be decrypted in order to work.
@@ -22,11 +28,11 @@ impl EncryptedResource {
     /// Decrypt the given encrypted resource
     ///
     /// # Errors
-    /// An error may occur if the encrypted resource could not be decrypted successfully.
+    /// A [`DebugError::ResourceDecryptError`](crate::error::DebugError::ResourceDecryptError)
+    /// occurs if the encrypted resource is truncated or fails authentication.
     pub fn decrypt(self) -> Result<Resource> {
         Ok(Resource {
-            //TODO
-            source: self.source.to_vec(),
+            source: cipher::open(&RESOURCE_KEY, self.source)?,
         })
     }
 }
@@ -47,26 +53,75 @@ impl Display for Resource {
 
 #[cfg(test)]
 mod unit {
-    use crate::{resources::EncryptedResource, VERSION};
+    use crate::{
+        error::DebugError,
+        resources::{cipher, DecryptError, EncryptedResource, RESOURCE_KEY},
+        VERSION,
+    };
+
+    fn sealed(plaintext: &[u8]) -> EncryptedResource {
+        EncryptedResource {
+            source: Box::leak(
+                cipher::seal(&RESOURCE_KEY, &[7; cipher::NONCE_LEN], plaintext).into(),
+            ),
+        }
+    }
 
     #[test]
     fn decrypt() {
-        const TEST_FILE: EncryptedResource = EncryptedResource {
-            source: b"Hello World!",
-        };
         let expected: String = "Hello World!".to_string();
 
-        assert_eq!(TEST_FILE.decrypt().unwrap().to_string(), expected);
+        assert_eq!(
+            sealed(b"Hello World!").decrypt().unwrap().to_string(),
+            expected
+        );
     }
 
     #[test]
     fn replace_version() {
-        const TEST_FILE: EncryptedResource = EncryptedResource {
-            source: b"_KIC = {\n    version = \"!<!<VERSION>!>!\"\n}\n",
-        };
+        let test_file = sealed(b"_KIC = {\n    version = \"!<!<VERSION>!>!\"\n}\n");
 
         let expected: String = format!("_KIC = {{\n    version = \"{VERSION}\"\n}}\n");
 
-        assert_eq!(TEST_FILE.decrypt().unwrap().to_string(), expected);
+        assert_eq!(test_file.decrypt().unwrap().to_string(), expected);
+    }
+
+    #[test]
+    fn tampering_is_detected() {
+        let mut source = cipher::seal(&RESOURCE_KEY, &[7; cipher::NONCE_LEN], b"Hello World!");
+        let last = source.len() - 1;
+        for index in [0, cipher::NONCE_LEN, last] {
+            source[index] ^= 0x01;
+            let test_file = EncryptedResource {
+                source: Box::leak(source.clone().into()),
+            };
+            assert!(matches!(
+                test_file.decrypt(),
+                Err(DebugError::ResourceDecryptError {
+                    source: DecryptError::Tampered
+                })
+            ));
+            source[index] ^= 0x01;
+        }
+
+        let truncated = EncryptedResource {
+            source: Box::leak(source[..cipher::NONCE_LEN].into()),
+        };
+        assert!(matches!(
+            truncated.decrypt(),
+            Err(DebugError::ResourceDecryptError {
+                source: DecryptError::Truncated
+            })
+        ));
+    }
+
+    #[test]
+    fn embedded_resources_decrypt() {
+        assert!(crate::resources::KIDEBUGGER_TSP
+            .decrypt()
+            .unwrap()
+            .to_string()
+            .contains("kiExecuteWithDebugger"));
+        assert!(crate::resources::TSPDBG_TSP.decrypt().is_ok());
     }
 }
@@ -0,0 +1,401 @@
+//! Run the `test_*` functions of TSP scripts on the instrument and report the results
+//! as a terminal summary and as JUnit XML.
+//!
+//! Each script is uploaded, run in its own environment and then each of its global
+//! functions named `test_*` is called under `xpcall`, in alphabetical order. A test
+//! passes if it returns without raising an error.
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use kic_lib::{
+    instrument::{clear_output_queue, read_until, Instrument},
+    InstrumentError,
+};
+
+use crate::{
+    debugger::script_name::script_name,
+    error::{DebugError, Result},
+};
+
+/// Marks the end of the results of a script printed by the instrument.
+pub const RESULTS_END: &str = "</kic-test-run>";
+
+/// The start of the line that begins the result of a test.
+const RESULT_BEGIN: &str = "<kic-test ";
+/// The line that ends the result of a test.
+const RESULT_END: &str = "</kic-test>";
+
+/// How often the instrument output is read while the tests run.
+const READ_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How many times the output of aborted tests is read until it is cleared.
+const ABORT_CLEAR_ATTEMPTS: usize = 500;
+
+/// The outcome of a test.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Passed,
+    /// The test raised an error, with its traceback
+    Failed(String),
+    /// The script could not be loaded or its tests could not be run
+    Error(String),
+}
+
+/// The result of a test.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestCase {
+    pub name: String,
+    /// How long the test took in seconds
+    pub time: f64,
+    pub outcome: Outcome,
+}
+
+/// The results of the tests in one script.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestSuite {
+    /// The path of the script
+    pub name: String,
+    pub cases: Vec<TestCase>,
+}
+
+impl TestSuite {
+    fn count(&self, matches: impl Fn(&Outcome) -> bool) -> usize {
+        self.cases.iter().filter(|c| matches(&c.outcome)).count()
+    }
+
+    /// The number of tests that failed.
+    #[must_use]
+    pub fn failures(&self) -> usize {
+        self.count(|o| matches!(o, Outcome::Failed(_)))
+    }
+
+    /// The number of scripts or tests that could not be run.
+    #[must_use]
+    pub fn errors(&self) -> usize {
+        self.count(|o| matches!(o, Outcome::Error(_)))
+    }
+
+    fn time(&self) -> f64 {
+        self.cases.iter().map(|c| c.time).sum()
+    }
+}
+
+/// Find the scripts to test: `path` itself if it is a file, or the `.tsp` and `.lua`
+/// files below it if it is a directory.
+///
+/// # Errors
+/// Returns an IO error if a directory can't be read.
+pub fn discover(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut scripts = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            scripts.extend(discover(&path)?);
+        } else if path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("tsp") || e.eq_ignore_ascii_case("lua"))
+        {
+            scripts.push(path);
+        }
+    }
+    scripts.sort();
+    Ok(scripts)
+}
+
+/// The TSP command that runs the tests of the uploaded script `name` and prints the
+/// results.
+#[must_use]
+pub fn runner(name: &str) -> String {
+    [
+        "do",
+        "local function report(test, status, time, message)",
+        "print(\"<kic-test name='\" .. test .. \"' status='\" .. status .. \"' time='\" .. string.format(\"%.6f\", time) .. \"'>\")",
+        "if message then print(tostring(message)) end",
+        "print(\"</kic-test>\")",
+        "end",
+        "local clock = (os and os.clock) or function() return 0 end",
+        "local traceback = (debug and debug.traceback) or tostring",
+        "local env = setmetatable({}, { __index = _G })",
+        // A script with a syntax error isn't created, the error is in the error queue.
+        &format!("local chunk, err = nil, \"the script could not be loaded\" if {name} then chunk, err = loadstring({name}.source, \"{name}\")"),
+        "elseif errorqueue and errorqueue.count > 0 then local _ _, err = errorqueue.next() end",
+        "if chunk then setfenv(chunk, env) local ok ok, err = xpcall(chunk, traceback) if ok then err = nil end end",
+        "if err then report(\"(load)\", \"error\", 0, err) else",
+        "local tests = {}",
+        "for k, v in pairs(env) do if type(v) == \"function\" and string.sub(k, 1, 5) == \"test_\" then table.insert(tests, k) end end",
+        "table.sort(tests)",
+        "for _, test in ipairs(tests) do",
+        "local start = clock()",
+        "local ok, e = xpcall(env[test], traceback)",
+        "report(test, ok and \"passed\" or \"failed\", clock() - start, (not ok) and e or nil)",
+        "end",
+        "end",
+        &format!("print(\"{RESULTS_END}\")"),
+        "end\n",
+    ]
+    .join(" ")
+}
+
+/// Parse the results printed by [`runner`]. Other output, e.g. from `print` calls in
+/// the tests, is ignored.
+#[must_use]
+pub fn parse(suite: &str, output: &str) -> TestSuite {
+    let mut cases = Vec::new();
+    let mut lines = output.lines();
+    while let Some(line) = lines.next() {
+        let Some(attributes) = line.trim().strip_prefix(RESULT_BEGIN) else {
+            continue;
+        };
+        let attribute = |name: &str| {
+            attributes
+                .split_once(&format!("{name}='"))
+                .and_then(|(_, rest)| rest.split_once('\''))
+                .map(|(value, _)| value.to_string())
+        };
+        let mut message = Vec::new();
+        for line in lines.by_ref() {
+            if line.trim() == RESULT_END {
+                break;
+            }
+            message.push(line);
+        }
+        // The traceback continues into the runner, which isn't of interest.
+        if let Some(end) = message
+            .iter()
+            .position(|l| l.contains("in function 'xpcall'"))
+        {
+            message.truncate(end);
+        }
+        let message = message.join("\n");
+        cases.push(TestCase {
+            name: attribute("name").unwrap_or_default(),
+            time: attribute("time")
+                .and_then(|t| t.parse().ok())
+                .unwrap_or(0.0),
+            outcome: match attribute("status").as_deref() {
+                Some("passed") => Outcome::Passed,
+                Some("failed") => Outcome::Failed(message),
+                _ => Outcome::Error(message),
+            },
+        });
+    }
+    TestSuite {
+        name: suite.to_string(),
+        cases,
+    }
+}
+
+/// Upload the script at `path` and run its tests.
+///
+/// If the tests don't finish within `timeout`, they are aborted and reported as an
+/// error.
+///
+/// # Errors
+/// Returns an IO error if the script can't be read or the instrument can't be written.
+pub fn run_file(
+    instrument: &mut dyn Instrument,
+    path: &Path,
+    timeout: Duration,
+) -> Result<TestSuite> {
+    let source = fs::read_to_string(path)?;
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let name = script_name(&stem, &path.display().to_string());
+
+    instrument.write_script(name.as_bytes(), source.as_bytes(), false, false)?;
+    instrument.write_all(runner(&name).as_bytes())?;
+    let attempts = usize::try_from(timeout.as_millis() / READ_INTERVAL.as_millis())
+        .unwrap_or(1)
+        .max(1);
+    let suite = match read_until(
+        instrument,
+        &[RESULTS_END.to_string()],
+        attempts,
+        READ_INTERVAL,
+    ) {
+        Ok(output) => parse(&path.display().to_string(), &output),
+        // `read_until` gives up with an empty `Other`.
+        Err(InstrumentError::Other(details)) if details.is_empty() => {
+            instrument.write_all(b"abort\n")?;
+            // What the aborted tests still print must not be read with the next script.
+            clear_output_queue(instrument, ABORT_CLEAR_ATTEMPTS, READ_INTERVAL)?;
+            TestSuite {
+                name: path.display().to_string(),
+                cases: vec![TestCase {
+                    name: "(timeout)".to_string(),
+                    time: timeout.as_secs_f64(),
+                    outcome: Outcome::Error(format!(
+                        "the tests did not finish within {} s and were aborted",
+                        timeout.as_secs()
+                    )),
+                }],
+            }
+        }
+        Err(e) => return Err(DebugError::from(e)),
+    };
+    instrument.write_all(format!("{name} = nil\nscript.delete(\"{name}\")\n").as_bytes())?;
+    Ok(suite)
+}
+
+/// Render the results as JUnit XML.
+#[must_use]
+pub fn junit(suites: &[TestSuite]) -> String {
+    let tests: usize = suites.iter().map(|s| s.cases.len()).sum();
+    let failures: usize = suites.iter().map(TestSuite::failures).sum();
+    let errors: usize = suites.iter().map(TestSuite::errors).sum();
+    let time: f64 = suites.iter().map(TestSuite::time).sum();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuites tests=\"{tests}\" failures=\"{failures}\" errors=\"{errors}\" time=\"{time:.6}\">"
+    );
+    for suite in suites {
+        let _ = writeln!(
+            xml,
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.6}\">",
+            escape(&suite.name),
+            suite.cases.len(),
+            suite.failures(),
+            suite.errors(),
+            suite.time()
+        );
+        for case in &suite.cases {
+            let _ = write!(
+                xml,
+                "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.6}\"",
+                escape(&case.name),
+                escape(&suite.name),
+                case.time
+            );
+            match &case.outcome {
+                Outcome::Passed => xml.push_str(" />\n"),
+                Outcome::Failed(message) | Outcome::Error(message) => {
+                    let element = if matches!(case.outcome, Outcome::Failed(_)) {
+                        "failure"
+                    } else {
+                        "error"
+                    };
+                    let _ = writeln!(
+                        xml,
+                        ">\n      <{element} message=\"{}\">{}</{element}>\n    </testcase>",
+                        escape(message.lines().next().unwrap_or_default()),
+                        escape(message)
+                    );
+                }
+            }
+        }
+        xml.push_str("  </testsuite>\n");
+    }
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+/// Render the results as a summary for the terminal.
+#[must_use]
+pub fn summary(suites: &[TestSuite]) -> String {
+    let mut summary = String::new();
+    let (mut passed, mut failed) = (0, 0);
+    for suite in suites {
+        for case in &suite.cases {
+            match &case.outcome {
+                Outcome::Passed => passed += 1,
+                Outcome::Failed(message) | Outcome::Error(message) => {
+                    failed += 1;
+                    let _ = writeln!(summary, "FAILED {} {}", suite.name, case.name);
+                    for line in message.lines() {
+                        let _ = writeln!(summary, "    {line}");
+                    }
+                }
+            }
+        }
+    }
+    let _ = write!(summary, "{passed} passed, {failed} failed");
+    summary
+}
+
+/// Escape text for XML.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod unit {
+    use std::{io::Write, path::Path, time::Duration};
+
+    use kic_lib::{interface::NonBlock, model::simulator};
+
+    use super::{junit, parse, run_file, Outcome};
+
+    #[test]
+    fn results_are_parsed_and_rendered() {
+        let output = "hello\n<kic-test name='test_a' status='passed' time='0.5'>\n</kic-test>\n<kic-test name='test_b' status='failed' time='0.25'>\nt.tsp:3: x < 1\nstack traceback:\n</kic-test>\n</kic-test-run>";
+        let suite = parse("t.tsp", output);
+
+        assert_eq!(suite.cases.len(), 2);
+        assert_eq!(suite.cases[0].outcome, Outcome::Passed);
+        assert_eq!(
+            suite.cases[1].outcome,
+            Outcome::Failed("t.tsp:3: x < 1\nstack traceback:".to_string())
+        );
+        let xml = junit(&[suite]);
+        assert!(
+            xml.contains("<testsuites tests=\"2\" failures=\"1\" errors=\"0\" time=\"0.750000\">")
+        );
+        assert!(xml.contains("<failure message=\"t.tsp:3: x &lt; 1\">"));
+    }
+
+    #[test]
+    fn the_output_of_aborted_tests_is_cleared() {
+        let dir = std::env::temp_dir().join(format!("kic-test-timeout-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let slow = dir.join("slow.tsp");
+        std::fs::write(&slow, "function test_slow() delay(0.5) end\n").unwrap();
+        let quick = dir.join("quick.tsp");
+        std::fs::write(&quick, "function test_quick() end\n").unwrap();
+        let mut instrument = simulator::Instrument::new().unwrap();
+        instrument.set_nonblocking(true).unwrap();
+
+        let timed_out = run_file(&mut instrument, &slow, Duration::from_millis(100)).unwrap();
+        let next = run_file(&mut instrument, &quick, Duration::from_secs(5)).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(timed_out.cases[0].name, "(timeout)");
+        let names: Vec<&str> = next.cases.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["test_quick"]);
+    }
+
+    #[test]
+    fn tests_run_on_the_simulator() {
+        let dir = std::env::temp_dir().join(format!("kic-test-runner-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("suite.tsp");
+        std::fs::File::create(&path)
+            .unwrap()
+            .write_all(b"function test_ok() assert(1 + 1 == 2) end\nfunction test_fails() error(\"boom\") end\nfunction helper() end\n")
+            .unwrap();
+        let mut instrument = simulator::Instrument::new().unwrap();
+        instrument.set_nonblocking(true).unwrap();
+
+        let suite = run_file(&mut instrument, Path::new(&path), Duration::from_secs(5)).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let names: Vec<&str> = suite.cases.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["test_fails", "test_ok"]);
+        assert!(matches!(&suite.cases[0].outcome, Outcome::Failed(m) if m.contains("boom")));
+        assert_eq!(suite.cases[1].outcome, Outcome::Passed);
+    }
+}
@@ -1,7 +1,7 @@
 //! All the errors that this crate can emit are defined in the
 //! [`error::InstrumentError`] enum.
 
-use std::{net::AddrParseError, num::ParseIntError, string::FromUtf8Error};
+use std::{net::AddrParseError, num::ParseIntError};
 
 use thiserror::Error;
 
@@ -36,14 +36,6 @@ pub enum InstrumentError {
         details: String,
     },
 
-    /// A resource file was unable to be decrypted.
-    #[error("unable to decrypt resource: {source}")]
-    ResourceDecryptError {
-        ///**TODO:** Change this to the error that is produced when decryption fails
-        #[from]
-        source: FromUtf8Error,
-    },
-
     /// An error that occurs while trying to retrieve information about an instrument
     /// such as the serial number, model, manufacturer, etc.
     #[error("instrument information retrieval error: {details}")]
@@ -11,6 +11,8 @@ use crate::{
 
 pub mod ki2600;
 pub mod ki3700;
+#[cfg(feature = "simulator")]
+pub mod simulator;
 pub mod tti;
 pub mod versatest;
 
@@ -0,0 +1,296 @@
+-- Emulates the parts of the TSP runtime that the debugger scripts and simple user
+-- scripts rely on. This chunk is run once in a fresh interpreter and returns the
+-- function that executes each line sent to the simulator.
+--
+-- The host passes its primitives:
+--   host.write(text)    append text to the output of the instrument
+--   host.sleep(seconds) block for the given time
+--   host.getmessage()   the next line sent to the instrument (with "\n"), or nil
+--   host.aborted()      whether "abort" was sent since the last call
+--   host.model, host.serialno, host.revision
+local host = ...
+
+-- Lua 5.0 functions that TSP still has
+math.mod = math.mod or math.fmod
+string.gfind = string.gfind or string.gmatch
+table.getn = table.getn or function(t) return #t end
+
+-- TSP can iterate a table directly (`for k in t do`) as Lua 5.0 did, so chunks are
+-- rewritten to iterate tables through `_tspIterate`.
+function _tspIterate(iterable)
+    if type(iterable) == "table" then
+        local mt = getmetatable(iterable)
+        if not (mt and mt.__call) then
+            return next, iterable
+        end
+    end
+    return iterable
+end
+
+-- The source of each chunk, handed out by `string.dump` below
+local sources = setmetatable({}, { __mode = "k" })
+
+local lualoadstring = loadstring
+loadstring = function(source, name)
+    if type(source) == "string" then
+        source = string.gsub(source, "(for%s+[%w_,%s]-%s+in%s+)([%a_][%w_%.]*)(%s+do%f[^%w_])",
+            "%1_tspIterate(%2)%3")
+    end
+    local chunk, compilationError = lualoadstring(source, name)
+    if chunk then
+        sources[chunk] = source
+    end
+    return chunk, compilationError
+end
+
+-- The bytecode of this interpreter isn't the one TSP uses, so `string.dump` gives the
+-- source of a chunk instead. The debugger reads bytecode with `tspdbg:ChunkSpy` to find
+-- the globals a chunk uses, which is replaced by one that lists the names in the
+-- source. Some of them aren't variables, and looking them up does no harm.
+string.dump = function(f)
+    local source = sources[f]
+    if not source then
+        error("string.dump is not supported by the simulator", 2)
+    end
+    return source
+end
+
+local function chunkSpy(_, _, source)
+    local k, inst = {}, {}
+    for name in string.gfind(source, "[%a_][%w_]*") do
+        table.insert(k, name)
+        table.insert(inst, { opname = "getglobal", Bx = table.getn(k) - 1 })
+    end
+    return { func = { k = k, inst = inst } }
+end
+
+-- tspdbg publishes itself as `_G[".tspdbg"]` before it defines its functions.
+setmetatable(_G, {
+    __newindex = function(g, name, value)
+        rawset(g, name, value)
+        if name == ".tspdbg" and type(value) == "table" then
+            setmetatable(value, {
+                __newindex = function(tspdbg, field, f)
+                    rawset(tspdbg, field, field == "ChunkSpy" and chunkSpy or f)
+                end,
+            })
+        end
+    end,
+})
+
+-- TSP doesn't have the functions that Lua 5.1 added. Files on the computer are out of
+-- reach of the scripts.
+local select = select
+for _, name in ipairs({ "module", "require", "package", "load", "select", "newproxy",
+    "dofile", "loadfile" }) do
+    _G[name] = nil
+end
+
+-- Only the time is left of `os`. There is no environment on an instrument.
+os = { clock = os.clock, date = os.date, difftime = os.difftime, time = os.time,
+    getenv = function() return nil end }
+
+print = function(...)
+    local parts = {}
+    for i = 1, select("#", ...) do
+        parts[i] = tostring((select(i, ...)))
+    end
+    host.write(table.concat(parts, "\t") .. "\n")
+end
+
+delay = function(seconds)
+    host.sleep(tonumber(seconds) or 0)
+end
+
+localnode = {
+    model = host.model,
+    serialno = host.serialno,
+    revision = host.revision,
+    prompts = 0,
+    -- Unlike on an instrument, errors are printed by default.
+    showerrors = 1,
+}
+
+local errors = {}
+errorqueue = { count = 0 }
+
+local function queueerror(code, message)
+    table.insert(errors, { code, message })
+    errorqueue.count = table.getn(errors)
+    if localnode.showerrors == 1 then
+        print(message)
+    end
+end
+
+function errorqueue.next()
+    local e = table.remove(errors, 1)
+    errorqueue.count = table.getn(errors)
+    if not e then
+        return 0, "Queue Is Empty", 0, 1
+    end
+    return e[1], e[2], 1, 1
+end
+
+function errorqueue.clear()
+    errors = {}
+    errorqueue.count = 0
+end
+
+-- There are no instrument commands that could fail, so scripts and mocks queue errors
+-- themselves, e.g. `simulator.queueerror(-222, "Parameter data out of range")`.
+simulator = { queueerror = queueerror }
+
+ki = {
+    remotecomm = {
+        intercept = 0,
+        getmessage = function()
+            return host.getmessage()
+        end,
+    },
+    aborthook = function()
+        if host.aborted() then
+            error("Script aborted", 2)
+        end
+    end,
+}
+
+-- Scripts are kept in `script.user.scripts`, the saved ones are also in the catalog.
+local scripts = {}
+local saved = {}
+script = { user = { scripts = scripts } }
+
+local function catalog()
+    local names = {}
+    for name in pairs(saved) do
+        table.insert(names, name)
+    end
+    table.sort(names)
+    local i = 0
+    return function()
+        i = i + 1
+        return names[i]
+    end
+end
+
+local function newscript(source, name)
+    local chunk, compilationError = loadstring(source, name)
+    if not chunk then
+        return nil, compilationError
+    end
+    local save = function()
+        if name then saved[name] = true end
+    end
+    local s = { source = source, name = name, run = chunk, save = save }
+    -- Script objects are recognized by their metatable on an instrument.
+    setmetatable(s, {
+        Getters = {},
+        Setters = {},
+        Objects = { source = source },
+        name = name,
+        run = chunk,
+        __call = function() return chunk() end,
+    })
+    if name then
+        scripts[name] = s
+        _G[name] = s
+    end
+    return s
+end
+
+function script.new(source, name)
+    return (assert(newscript(source, name)))
+end
+
+function script.delete(name)
+    scripts[name] = nil
+    saved[name] = nil
+end
+
+script.catalog = catalog
+script.user.catalog = catalog
+script.user.delete = script.delete
+
+-- The debugger leaves out tables that look like instrument objects, so mark the
+-- runtime's tables that way to keep them out of the globals it reports.
+for _, t in ipairs({ string, table, math, coroutine, debug, os, localnode,
+    errorqueue, ki, script, simulator }) do
+    setmetatable(t, { Getters = {}, Setters = {}, Objects = {} })
+end
+
+local function report(code, kind, message)
+    local _, _, line, text = string.find(tostring(message), '^%[string "[^"]*"%]:(%d+): (.*)$')
+    if line then
+        message = "TSP " .. kind .. " error at line " .. line .. ": " .. text
+    end
+    queueerror(code, tostring(message))
+end
+
+local function run(chunk)
+    local ok, runtimeError = pcall(chunk)
+    if not ok then
+        report(-286, "Runtime", runtimeError)
+    end
+end
+
+local function scpi(command)
+    command = string.upper(command)
+    if command == "*IDN?" then
+        print(string.format("Keithley Instruments,MODEL %s,%s,%s", localnode.model, localnode.serialno,
+            localnode.revision))
+    elseif command == "*CLS" then
+        errorqueue.clear()
+    elseif command ~= "*RST" then
+        queueerror(-113, "Undefined header")
+    end
+end
+
+local function prompt()
+    if localnode.prompts == 1 then
+        print(errorqueue.count > 0 and "TSP?" or "TSP>")
+    end
+end
+
+-- The script that is being loaded between `loadscript` and `endscript`
+local loading
+
+return function(line)
+    line = string.gsub(line, "\r$", "")
+    if loading then
+        if string.find(line, "^%s*endscript%s*$") then
+            local name, source = loading.name, table.concat(loading.lines, "\n")
+            loading = nil
+            local _, compilationError = newscript(source, name)
+            if compilationError then
+                report(-285, "Syntax", compilationError)
+            end
+            prompt()
+        else
+            table.insert(loading.lines, line)
+        end
+        return
+    end
+
+    local _, _, name = string.find(line, "^%s*loadscript%s+([%a_][%w_]*)%s*$")
+    if name then
+        loading = { name = name, lines = {} }
+        return
+    end
+
+    if string.find(line, "^%s*abort%s*$") then
+        -- Nothing is running between lines, so there is nothing to abort.
+        host.aborted()
+    elseif string.find(line, "^%s*password") or string.find(line, "^%s*login") or
+        string.find(line, "^%s*logout") then
+        -- The simulator doesn't need a login.
+    elseif string.sub(line, 1, 1) == "*" then
+        scpi(string.gsub(line, "%s+$", ""))
+    elseif not string.find(line, "^%s*$") then
+        local chunk, compilationError = loadstring(line)
+        if chunk then
+            run(chunk)
+        else
+            report(-285, "Syntax", compilationError)
+        end
+    end
+    prompt()
+end
@@ -0,0 +1,369 @@
+//! An instrument that runs TSP in a Lua interpreter on the host, so that scripts and
+//! the debugger can be run without hardware.
+//!
+//! The TSP runtime is emulated just far enough for the debugger scripts to run
+//! unchanged: `localnode`, `script`, `loadscript ... endscript`, `print`, `delay`,
+//! `errorqueue` and `ki.remotecomm`. There is no instrument behind it, so scripts that
+//! measure or source anything need to be given mocks for those commands. A failing
+//! command can be stood in for with `simulator.queueerror(code, message)`, which queues
+//! an error like the instrument would.
+//!
+//! TSP is based on Lua 5.0 and the interpreter is Lua 5.1. Iterating a table directly
+//! (`for k in t do`) is rewritten to work. The bytecode isn't the one of TSP, so
+//! `string.dump` gives the source of a chunk and the debugger's bytecode reader is
+//! replaced by one that finds the names in it. This is enough for watchpoints and
+//! expressions, but not for anything that needs the line information of a chunk.
+//!
+//! Scripts only get the libraries that don't reach the computer: there is no `io`, and
+//! `os` only tells the time.
+use std::{
+    collections::VecDeque,
+    io::{ErrorKind, Read, Write},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver, Sender, TryRecvError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use mlua::{Function, Lua, LuaOptions, StdLib};
+use tracing::{error, trace};
+
+use crate::{
+    instrument::{self, info::InstrumentInfo, Abort, Info, Login, Reset, Script},
+    interface::NonBlock,
+    model::{Model, Vendor},
+    Flash, InstrumentError,
+};
+
+/// The Lua that emulates the TSP runtime.
+const PRELUDE: &str = include_str!("resources/simulator.lua");
+
+/// The model the simulator reports.
+pub const SIMULATOR_MODEL: &str = "SIMULATOR";
+
+/// How often a blocking read checks for new output.
+const READ_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+pub struct Instrument {
+    input: Option<Sender<String>>,
+    /// A line that was written without its line break yet
+    partial: String,
+    output: Arc<Mutex<VecDeque<u8>>>,
+    abort: Arc<AtomicBool>,
+    nonblocking: bool,
+}
+
+impl Instrument {
+    /// Start a simulator with a fresh interpreter.
+    ///
+    /// # Errors
+    /// Returns a [`InstrumentError::ConnectionError`] if the interpreter could not be
+    /// started.
+    pub fn new() -> Result<Self, InstrumentError> {
+        let (input, lines) = channel();
+        let (started, start_result) = channel();
+        let output = Arc::new(Mutex::new(VecDeque::new()));
+        let abort = Arc::new(AtomicBool::new(false));
+
+        let (thread_output, thread_abort) = (Arc::clone(&output), Arc::clone(&abort));
+        thread::Builder::new()
+            .name("tsp-simulator".to_string())
+            .spawn(move || run(lines, &thread_output, &thread_abort, &started))
+            .map_err(|e| InstrumentError::ConnectionError {
+                details: format!("unable to start the simulator: {e}"),
+            })?;
+
+        match start_result.recv() {
+            Ok(Ok(())) => Ok(Self {
+                input: Some(input),
+                partial: String::new(),
+                output,
+                abort,
+                nonblocking: false,
+            }),
+            Ok(Err(details)) => Err(InstrumentError::ConnectionError { details }),
+            Err(e) => Err(InstrumentError::ConnectionError {
+                details: format!("unable to start the simulator: {e}"),
+            }),
+        }
+    }
+
+    /// Queue a complete line for the interpreter.
+    fn send_line(&mut self, line: String) -> std::io::Result<()> {
+        if line.trim() == "abort" {
+            self.abort.store(true, Ordering::SeqCst);
+        }
+        self.input
+            .as_ref()
+            .and_then(|input| input.send(line).ok())
+            .ok_or_else(|| std::io::Error::from(ErrorKind::BrokenPipe))
+    }
+}
+
+/// Run the interpreter until the instrument is dropped.
+fn run(
+    lines: Receiver<String>,
+    output: &Arc<Mutex<VecDeque<u8>>>,
+    abort: &Arc<AtomicBool>,
+    started: &Sender<Result<(), String>>,
+) {
+    // SAFETY: The debugger scripts need the `debug` library, which is only loaded for
+    // unsafe interpreters. Only the scripts sent to the simulator run in it, and the
+    // prelude removes what would reach the computer from the other libraries.
+    let lua = unsafe {
+        Lua::unsafe_new_with(
+            StdLib::TABLE | StdLib::STRING | StdLib::MATH | StdLib::OS | StdLib::DEBUG,
+            LuaOptions::new(),
+        )
+    };
+    let lines = Rc::new(lines);
+    let execute = match start(&lua, &lines, output, abort) {
+        Ok(execute) => {
+            let _ = started.send(Ok(()));
+            execute
+        }
+        Err(e) => {
+            let _ = started.send(Err(format!("unable to start the simulator: {e}")));
+            return;
+        }
+    };
+
+    while let Ok(line) = lines.recv() {
+        trace!("simulating: '{line}'");
+        if let Err(e) = execute.call::<_, ()>(line) {
+            error!("simulator error: {e}");
+        }
+    }
+}
+
+/// Install the host primitives and run the prelude.
+///
+/// # Returns
+/// The function that executes a line sent to the simulator.
+fn start<'lua>(
+    lua: &'lua Lua,
+    lines: &Rc<Receiver<String>>,
+    output: &Arc<Mutex<VecDeque<u8>>>,
+    abort: &Arc<AtomicBool>,
+) -> mlua::Result<Function<'lua>> {
+    let host = lua.create_table()?;
+
+    let output = Arc::clone(output);
+    host.set(
+        "write",
+        lua.create_function(move |_, text: mlua::String| {
+            if let Ok(mut output) = output.lock() {
+                output.extend(text.as_bytes());
+            }
+            Ok(())
+        })?,
+    )?;
+    host.set(
+        "sleep",
+        lua.create_function(|_, seconds: f64| {
+            if seconds.is_finite() && seconds > 0.0 {
+                thread::sleep(Duration::from_secs_f64(seconds));
+            }
+            Ok(())
+        })?,
+    )?;
+    let messages = Rc::clone(lines);
+    host.set(
+        "getmessage",
+        lua.create_function(move |_, ()| match messages.try_recv() {
+            Ok(line) => Ok(Some(format!("{line}\n"))),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(mlua::Error::RuntimeError(
+                "the simulator was closed".to_string(),
+            )),
+        })?,
+    )?;
+    let abort = Arc::clone(abort);
+    host.set(
+        "aborted",
+        lua.create_function(move |_, ()| Ok(abort.swap(false, Ordering::SeqCst)))?,
+    )?;
+    host.set("model", SIMULATOR_MODEL)?;
+    host.set("serialno", "0")?;
+    host.set("revision", env!("CARGO_PKG_VERSION"))?;
+
+    lua.load(PRELUDE).set_name("simulator").call(host)
+}
+
+impl instrument::Instrument for Instrument {}
+
+impl Info for Instrument {
+    fn info(&mut self) -> crate::error::Result<InstrumentInfo> {
+        Ok(InstrumentInfo {
+            vendor: Vendor::Keithley,
+            model: Model::Other(SIMULATOR_MODEL.to_string()),
+            serial_number: "0".to_string(),
+            firmware_rev: Some(env!("CARGO_PKG_VERSION").to_string()),
+        })
+    }
+}
+
+impl instrument::Language for Instrument {}
+
+impl Login for Instrument {}
+
+impl Script for Instrument {}
+
+impl Reset for Instrument {}
+
+impl Abort for Instrument {}
+
+impl Flash for Instrument {
+    fn flash_firmware(&mut self, _: &[u8], _: Option<u16>) -> crate::error::Result<()> {
+        Err(InstrumentError::FwUpgradeFailure(
+            "the simulator has no firmware to upgrade".to_string(),
+        ))
+    }
+}
+
+impl Read for Instrument {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            {
+                let mut output = self
+                    .output
+                    .lock()
+                    .map_err(|_| std::io::Error::from(ErrorKind::BrokenPipe))?;
+                if !output.is_empty() {
+                    let len = output.len().min(buf.len());
+                    for (b, o) in buf.iter_mut().zip(output.drain(..len)) {
+                        *b = o;
+                    }
+                    return Ok(len);
+                }
+            }
+            if self.nonblocking {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            thread::sleep(READ_POLL_INTERVAL);
+        }
+    }
+}
+
+impl Write for Instrument {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.partial.push_str(&String::from_utf8_lossy(buf));
+        while let Some(end) = self.partial.find('\n') {
+            let line: String = self.partial.drain(..=end).collect();
+            self.send_line(line.trim_end_matches(['\n', '\r']).to_string())?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl NonBlock for Instrument {
+    fn set_nonblocking(&mut self, enable: bool) -> crate::error::Result<()> {
+        self.nonblocking = enable;
+        Ok(())
+    }
+}
+
+impl Drop for Instrument {
+    fn drop(&mut self) {
+        // Stop a running script and let the interpreter thread end.
+        self.abort.store(true, Ordering::SeqCst);
+        self.input = None;
+    }
+}
+
+#[cfg(test)]
+mod unit {
+    use std::{io::Write, time::Duration};
+
+    use crate::{
+        instrument::{read_until, Info, Script},
+        interface::NonBlock,
+    };
+
+    use super::Instrument;
+
+    fn simulator() -> Instrument {
+        let mut instrument = Instrument::new().unwrap();
+        instrument.set_nonblocking(true).unwrap();
+        instrument
+    }
+
+    fn read(instrument: &mut Instrument, end: &str) -> String {
+        read_until(
+            instrument,
+            &[end.to_string()],
+            100,
+            Duration::from_millis(10),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn run_scripts() {
+        let mut instrument = simulator();
+
+        instrument
+            .write_script(b"hello", b"x = 6\nprint(\"x\", x * 7)", true, true)
+            .unwrap();
+        instrument
+            .write_all(b"for n in script.user.catalog() do print(n) end print(hello.source)\n")
+            .unwrap();
+
+        let output = read(&mut instrument, "x = 6");
+        assert!(output.starts_with("x\t42\nhello\n"), "{output}");
+        assert!(instrument.info().is_ok());
+    }
+
+    #[test]
+    fn errors_are_queued() {
+        let mut instrument = simulator();
+
+        instrument
+            .write_all(b"x = \nnil_function()\nprint(errorqueue.count)\n")
+            .unwrap();
+
+        let output = read(&mut instrument, "2");
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(
+            lines[0].starts_with("TSP Syntax error at line 1:"),
+            "{output}"
+        );
+        assert!(
+            lines[1].starts_with("TSP Runtime error at line 1:"),
+            "{output}"
+        );
+    }
+
+    #[test]
+    fn the_computer_is_out_of_reach() {
+        let mut instrument = simulator();
+
+        instrument
+            .write_all(b"print(io, os.execute, os.getenv(\"PATH\"), dofile, type(os.time()))\n")
+            .unwrap();
+
+        assert_eq!(
+            read(&mut instrument, "number"),
+            "nil\tnil\tnil\tnil\tnumber"
+        );
+    }
+
+    #[test]
+    fn remote_commands_reach_a_running_script() {
+        let mut instrument = simulator();
+
+        instrument
+            .write_all(b"ki.remotecomm.intercept = 1 repeat m = ki.remotecomm.getmessage() delay(0.001) until m print(\"got \" .. m)\nhello\n")
+            .unwrap();
+
+        assert_eq!(read(&mut instrument, "got"), "got hello");
+    }
+}